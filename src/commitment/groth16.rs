@@ -0,0 +1,223 @@
+use super::{COMMITMENT_DOMAIN, NULLIFIER_DOMAIN, OWNER_DOMAIN};
+use crate::common::groth16::WrappedLc;
+use crate::merkle::groth16::{check_proof, AllocatedProof};
+use crate::poseidon::groth16::{poseidon_lcs, GadgetHasher};
+use crate::BellmanFr;
+
+use bazuka::zk::ZkScalar;
+use bellman::gadgets::boolean::AllocatedBit;
+use bellman::gadgets::num::AllocatedNum;
+use bellman::{ConstraintSystem, SynthesisError};
+
+// H(tag, vals...), the domain tag is a constant and costs no constraints
+fn hash_tagged<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    tag: ZkScalar,
+    vals: &[AllocatedNum<BellmanFr>],
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    let mut elems = vec![WrappedLc::constant::<CS>(tag.into())];
+    elems.extend(vals.iter().cloned().map(WrappedLc::alloc_num));
+    poseidon_lcs(cs, elems)
+}
+
+// Same as native owner_key
+pub fn owner_key<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    secret: AllocatedNum<BellmanFr>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    hash_tagged(cs, *OWNER_DOMAIN, &[secret])
+}
+
+// Same as native commit
+pub fn commit<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    value: AllocatedNum<BellmanFr>,
+    owner: AllocatedNum<BellmanFr>,
+    blinding: AllocatedNum<BellmanFr>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    hash_tagged(cs, *COMMITMENT_DOMAIN, &[value, owner, blinding])
+}
+
+// Prove that commitment opens to value for owner with blinding
+pub fn assert_opening<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    commitment: AllocatedNum<BellmanFr>,
+    value: AllocatedNum<BellmanFr>,
+    owner: AllocatedNum<BellmanFr>,
+    blinding: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    let expected = commit(&mut cs.namespace(|| "commit"), value, owner, blinding)?;
+    cs.enforce(
+        || "commitment == expected",
+        |lc| lc + commitment.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + expected.get_variable(),
+    );
+    Ok(())
+}
+
+// Same as native nullifier, for the note committing to value, owner_key(secret) and
+// blinding, proven to be the leaf at position of the 4-ary tree of root (Hashed with H)
+// Since position comes out of the membership proof rather than being chosen by the
+// prover, a note (Its commitment being in the tree once) has exactly one nullifier
+// Unsatisfiable when enabled and the note isn't at position
+#[allow(clippy::too_many_arguments)]
+pub fn nullifier<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    value: AllocatedNum<BellmanFr>,
+    secret: AllocatedNum<BellmanFr>,
+    blinding: AllocatedNum<BellmanFr>,
+    position: AllocatedNum<BellmanFr>,
+    proof: AllocatedProof<LOG4_TREE_SIZE>,
+    root: AllocatedNum<BellmanFr>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    let owner = owner_key(&mut cs.namespace(|| "owner"), secret.clone())?;
+    let commitment = commit(&mut cs.namespace(|| "commit"), value, owner, blinding)?;
+    check_proof::<H, _, LOG4_TREE_SIZE>(
+        &mut cs.namespace(|| "membership"),
+        enabled,
+        position.clone(),
+        commitment,
+        proof,
+        root,
+    )?;
+    hash_tagged(
+        &mut cs.namespace(|| "hash"),
+        *NULLIFIER_DOMAIN,
+        &[secret, position],
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::merkle::Proof;
+    use bazuka::zk::{PoseidonHasher, ZkDataLocator, ZkStateBuilder, ZkStateModel};
+    use bellman::gadgets::test::TestConstraintSystem;
+
+    fn alloc<CS: ConstraintSystem<BellmanFr>>(cs: &mut CS, val: u64) -> AllocatedNum<BellmanFr> {
+        AllocatedNum::alloc(cs, || Ok(BellmanFr::from(val))).unwrap()
+    }
+
+    #[test]
+    fn test_commitment_opening() {
+        let owner = crate::commitment::owner_key(ZkScalar::from(777));
+        let commitment =
+            crate::commitment::commit(ZkScalar::from(100), owner, ZkScalar::from(12345));
+        for (value, owner_delta, blinding, valid) in [
+            (100, 0, 12345, true),
+            (101, 0, 12345, false),
+            (100, 1, 12345, false),
+            (100, 0, 1, false),
+        ] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let commitment_num =
+                AllocatedNum::alloc(cs.namespace(|| "commitment"), || Ok(commitment.into()))
+                    .unwrap();
+            let value = alloc(&mut cs.namespace(|| "value"), value);
+            let owner_num = AllocatedNum::alloc(cs.namespace(|| "owner"), || {
+                Ok((owner + ZkScalar::from(owner_delta)).into())
+            })
+            .unwrap();
+            let blinding = alloc(&mut cs.namespace(|| "blinding"), blinding);
+            assert_opening(
+                &mut cs.namespace(|| "opening"),
+                commitment_num,
+                value,
+                owner_num,
+                blinding,
+            )
+            .unwrap();
+            assert_eq!(cs.is_satisfied(), valid);
+        }
+    }
+
+    #[test]
+    fn test_nullifier() {
+        let secret = ZkScalar::from(777);
+        let owner = crate::commitment::owner_key(secret);
+        let commitment =
+            crate::commitment::commit(ZkScalar::from(100), owner, ZkScalar::from(12345));
+        let mut builder = ZkStateBuilder::<PoseidonHasher>::new(ZkStateModel::List {
+            log4_size: 2,
+            item_type: Box::new(ZkStateModel::Scalar),
+        });
+        builder
+            .set(ZkDataLocator(vec![3]), ZkScalar::from(5))
+            .unwrap();
+        builder.set(ZkDataLocator(vec![6]), commitment).unwrap();
+        let root = builder.get(ZkDataLocator(vec![])).unwrap();
+        let proof = Proof::<2>::try_from(builder.prove(ZkDataLocator(vec![]), 6).unwrap()).unwrap();
+
+        // Only the owner can derive the nullifier, and only at the note's position
+        for (secret_val, position, enabled, valid) in [
+            (777, 6, true, true),
+            (778, 6, true, false),
+            (777, 3, true, false),
+            (777, 7, true, false),
+            (778, 3, false, true),
+        ] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let value = alloc(&mut cs.namespace(|| "value"), 100);
+            let secret_num = alloc(&mut cs.namespace(|| "secret"), secret_val);
+            let blinding = alloc(&mut cs.namespace(|| "blinding"), 12345);
+            let position_num = alloc(&mut cs.namespace(|| "position"), position);
+            let proof =
+                AllocatedProof::alloc(&mut cs.namespace(|| "proof"), Some(proof.clone())).unwrap();
+            let root_num =
+                AllocatedNum::alloc(cs.namespace(|| "root"), || Ok(root.into())).unwrap();
+            let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(enabled)).unwrap();
+            let out = nullifier::<PoseidonHasher, _, 2>(
+                &mut cs.namespace(|| "nullifier"),
+                enabled,
+                value,
+                secret_num,
+                blinding,
+                position_num,
+                proof,
+                root_num,
+            )
+            .unwrap();
+            assert_eq!(
+                out.get_value(),
+                Some(
+                    crate::commitment::nullifier(
+                        ZkScalar::from(secret_val),
+                        ZkScalar::from(position)
+                    )
+                    .into()
+                )
+            );
+            assert_eq!(cs.is_satisfied(), valid);
+        }
+
+        // Different notes and different domains give different values
+        assert_ne!(
+            crate::commitment::nullifier(secret, ZkScalar::from(3)),
+            crate::commitment::nullifier(secret, ZkScalar::from(4))
+        );
+        assert_ne!(
+            crate::commitment::nullifier(secret, ZkScalar::from(3)),
+            crate::commitment::commit(secret, ZkScalar::from(3), ZkScalar::from(0))
+        );
+        assert_ne!(
+            owner,
+            crate::commitment::nullifier(secret, ZkScalar::from(0))
+        );
+    }
+
+    #[test]
+    fn test_domain_tags_are_free() {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let secret = alloc(&mut cs.namespace(|| "secret"), 777);
+        owner_key(&mut cs.namespace(|| "owner"), secret.clone()).unwrap();
+        let owner_constraints = cs.num_constraints();
+        crate::poseidon::groth16::poseidon(
+            &mut cs.namespace(|| "untagged"),
+            &[secret.clone(), secret],
+        )
+        .unwrap();
+        assert_eq!(cs.num_constraints(), 2 * owner_constraints);
+    }
+}
@@ -0,0 +1,37 @@
+#[cfg(feature = "groth16")]
+pub mod groth16;
+
+use bazuka::zk::{PoseidonHasher, ZkHasher, ZkScalar};
+use ff::PrimeField;
+
+// Domain tag out of an ascii name (At most 31 bytes, so that it fits in a field element)
+pub fn domain_tag(name: &str) -> ZkScalar {
+    assert!(name.len() < 32);
+    let mut repr = <ZkScalar as PrimeField>::Repr::default();
+    repr.as_mut()[..name.len()].copy_from_slice(name.as_bytes());
+    ZkScalar::from_repr(repr).unwrap()
+}
+
+lazy_static::lazy_static! {
+    pub static ref COMMITMENT_DOMAIN: ZkScalar = domain_tag("zeekit.commitment");
+    pub static ref NULLIFIER_DOMAIN: ZkScalar = domain_tag("zeekit.nullifier");
+    pub static ref OWNER_DOMAIN: ZkScalar = domain_tag("zeekit.owner");
+}
+
+// H(OWNER_DOMAIN, secret), the public key notes are committed to
+pub fn owner_key(secret: ZkScalar) -> ZkScalar {
+    PoseidonHasher::hash(&[*OWNER_DOMAIN, secret])
+}
+
+// H(COMMITMENT_DOMAIN, value, owner, blinding), owner being the owner_key of the
+// secret that can spend the note
+pub fn commit(value: ZkScalar, owner: ZkScalar, blinding: ZkScalar) -> ZkScalar {
+    PoseidonHasher::hash(&[*COMMITMENT_DOMAIN, value, owner, blinding])
+}
+
+// H(NULLIFIER_DOMAIN, secret, position), position being the index of the note
+// commitment in the tree. Since the commitment binds owner_key(secret), and the
+// gadget proves the commitment is at position, a note has exactly one nullifier
+pub fn nullifier(secret: ZkScalar, position: ZkScalar) -> ZkScalar {
+    PoseidonHasher::hash(&[*NULLIFIER_DOMAIN, secret, position])
+}
@@ -3,8 +3,8 @@ use crate::BellmanFr;
 use bellman::gadgets::boolean::{AllocatedBit, Boolean};
 use bellman::gadgets::num::AllocatedNum;
 use bellman::{ConstraintSystem, LinearCombination, SynthesisError};
-use ff::{Field, PrimeFieldBits};
-use std::ops::AddAssign;
+use ff::{Field, PrimeField, PrimeFieldBits};
+use std::ops::{Add, AddAssign, Neg, Sub};
 
 #[derive(Clone)]
 pub struct WrappedLc(pub LinearCombination<BellmanFr>, pub Option<BellmanFr>);
@@ -42,6 +42,68 @@ impl WrappedLc {
             Some(BellmanFr::zero()),
         )
     }
+    // self * coeff, no constraints
+    pub fn scale(&self, coeff: BellmanFr) -> WrappedLc {
+        WrappedLc(
+            LinearCombination::<BellmanFr>::zero() + (coeff, &self.0),
+            self.1.map(|v| v * coeff),
+        )
+    }
+    // self * other, 1 constraint
+    pub fn mul<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &WrappedLc,
+    ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+        let prod = AllocatedNum::alloc(&mut *cs, || {
+            self.1
+                .zip(other.1)
+                .map(|(a, b)| a * b)
+                .ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        cs.enforce(
+            || "self * other == prod",
+            |lc| lc + &self.0,
+            |lc| lc + &other.0,
+            |lc| lc + prod.get_variable(),
+        );
+        Ok(prod)
+    }
+}
+
+impl Add<&WrappedLc> for WrappedLc {
+    type Output = WrappedLc;
+    fn add(self, other: &WrappedLc) -> WrappedLc {
+        WrappedLc(self.0 + &other.0, self.1.zip(other.1).map(|(a, b)| a + b))
+    }
+}
+
+impl Add for WrappedLc {
+    type Output = WrappedLc;
+    fn add(self, other: WrappedLc) -> WrappedLc {
+        self + &other
+    }
+}
+
+impl Sub<&WrappedLc> for WrappedLc {
+    type Output = WrappedLc;
+    fn sub(self, other: &WrappedLc) -> WrappedLc {
+        WrappedLc(self.0 - &other.0, self.1.zip(other.1).map(|(a, b)| a - b))
+    }
+}
+
+impl Sub for WrappedLc {
+    type Output = WrappedLc;
+    fn sub(self, other: WrappedLc) -> WrappedLc {
+        self - &other
+    }
+}
+
+impl Neg for WrappedLc {
+    type Output = WrappedLc;
+    fn neg(self) -> WrappedLc {
+        self.scale(-BellmanFr::one())
+    }
 }
 
 pub fn mux<CS: ConstraintSystem<BellmanFr>>(
@@ -80,12 +142,322 @@ pub fn mux<CS: ConstraintSystem<BellmanFr>>(
             );
             ret
         }
-        Boolean::Constant(_) => {
-            unimplemented!();
+        Boolean::Constant(s) => {
+            let chosen = if *s { b } else { a };
+            let ret = AllocatedNum::alloc(&mut *cs, || {
+                chosen.get_value().ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            cs.enforce(
+                || "chosen == ret",
+                |lc| lc + &chosen.0,
+                |lc| lc + CS::one(),
+                |lc| lc + ret.get_variable(),
+            );
+            ret
         }
     })
 }
 
+// Select table[index], where index is given in little-endian bits and the table
+// has exactly 2^index_bits.len() entries, 2^index_bits.len() - 1 constraints
+pub fn select<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    index_bits: &[Boolean],
+    table: &[WrappedLc],
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    assert_eq!(table.len(), 1 << index_bits.len());
+    if index_bits.is_empty() {
+        // Table with a single entry, 1 constraint
+        return mux(cs, &Boolean::Constant(false), &table[0], &table[0]);
+    }
+    let mut layer = table.to_vec();
+    for (i, bit) in index_bits[..index_bits.len() - 1].iter().enumerate() {
+        let mut cs = cs.namespace(|| format!("layer {}", i));
+        let mut next_layer = Vec::new();
+        for (j, pair) in layer.chunks(2).enumerate() {
+            next_layer.push(WrappedLc::alloc_num(mux(
+                &mut cs.namespace(|| format!("mux {}", j)),
+                bit,
+                &pair[0],
+                &pair[1],
+            )?));
+        }
+        layer = next_layer;
+    }
+    mux(
+        &mut cs.namespace(|| "root"),
+        &index_bits[index_bits.len() - 1],
+        &layer[0],
+        &layer[1],
+    )
+}
+
+// Select from a table of constants, 2^(index_bits.len() - 1) - 1 constraints but
+// at least one (So 2-entry and 4-entry tables cost a single constraint)
+// The lowest index bit picks linearly between two constants, so the first layer
+// of muxes is free
+pub fn select_constant<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    index_bits: &[Boolean],
+    table: &[BellmanFr],
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    assert_eq!(table.len(), 1 << index_bits.len());
+    if index_bits.is_empty() {
+        return select(cs, &[], &[WrappedLc::constant::<CS>(table[0])]);
+    }
+    let s = &index_bits[0];
+    let layer = table
+        .chunks(2)
+        .map(|pair| {
+            let delta = pair[1] - pair[0];
+            let mut val = WrappedLc(
+                s.lc(CS::one(), delta),
+                s.get_value()
+                    .map(|s| if s { delta } else { BellmanFr::zero() }),
+            );
+            val.add_constant::<CS>(pair[0]);
+            val
+        })
+        .collect::<Vec<_>>();
+    select(cs, &index_bits[1..], &layer)
+}
+
+// Place v among p at the position given by the two little-endian index bits,
+// keeping the order of p, 5 constraints (Rather than 6 muxes and 2 Boolean ops)
+// With lt_k == (k < index) and w_k == lt_k * (p[k] - v), the outputs are linear:
+// (v + w_0, p[0] - w_0 + w_1, p[1] - w_1 + w_2, p[2] - w_2)
+pub fn insert<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    index_bits: &[Boolean; 2],
+    v: &WrappedLc,
+    p: &[WrappedLc; 3],
+) -> Result<[WrappedLc; 4], SynthesisError> {
+    let lt = [
+        Boolean::and(
+            cs.namespace(|| "nor"),
+            &index_bits[0].not(),
+            &index_bits[1].not(),
+        )?
+        .not(),
+        index_bits[1].clone(),
+        Boolean::and(cs.namespace(|| "and"), &index_bits[0], &index_bits[1])?,
+    ];
+    let mut w = Vec::new();
+    for (k, (lt, p)) in lt.iter().zip(p.iter()).enumerate() {
+        let lt = WrappedLc(
+            lt.lc(CS::one(), BellmanFr::one()),
+            lt.get_value().map(|b| BellmanFr::from(b as u64)),
+        );
+        w.push(WrappedLc::alloc_num(lt.mul(
+            &mut cs.namespace(|| format!("w {}", k)),
+            &(p.clone() - v),
+        )?));
+    }
+    Ok([
+        v.clone() + &w[0],
+        p[0].clone() - &w[0] + &w[1],
+        p[1].clone() - &w[1] + &w[2],
+        p[2].clone() - &w[2],
+    ])
+}
+
+// Returns (b, a) when swap is set, (a, b) otherwise, 1 constraint
+pub fn conditional_swap<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    swap: &Boolean,
+    a: &WrappedLc,
+    b: &WrappedLc,
+) -> Result<(WrappedLc, WrappedLc), SynthesisError> {
+    if let Boolean::Constant(swap) = swap {
+        return Ok(if *swap {
+            (b.clone(), a.clone())
+        } else {
+            (a.clone(), b.clone())
+        });
+    }
+    // delta = swap * (b - a), so that (a + delta, b - delta) is the result
+    let delta = AllocatedNum::alloc(&mut *cs, || {
+        swap.get_value()
+            .zip(a.get_value().zip(b.get_value()))
+            .map(|(swap, (a, b))| if swap { b - a } else { BellmanFr::zero() })
+            .ok_or(SynthesisError::AssignmentMissing)
+    })?;
+    cs.enforce(
+        || "swap * (b - a) == delta",
+        |lc| lc + &swap.lc(CS::one(), BellmanFr::one()),
+        |lc| lc + &b.0 - &a.0,
+        |lc| lc + delta.get_variable(),
+    );
+    let delta = WrappedLc::alloc_num(delta);
+    Ok((a.clone() + &delta, b.clone() - &delta))
+}
+
+// Compute switch settings of a Benes network which moves in[perm[j]] to out[j]
+// The order of the settings is the order in which benes_network consumes them
+fn benes_route(perm: &[usize], settings: &mut Vec<bool>) {
+    let n = perm.len();
+    if n <= 1 {
+        return;
+    }
+    if n == 2 {
+        settings.push(perm[0] == 1);
+        return;
+    }
+    let mut inv = vec![0; n];
+    for (j, i) in perm.iter().enumerate() {
+        inv[*i] = j;
+    }
+    // Which subnetwork (false: upper, true: lower) each input/output goes through
+    let mut in_sub = vec![false; n];
+    let mut out_sub: Vec<Option<bool>> = vec![None; n];
+    for start in (0..n).step_by(2) {
+        let mut j = start;
+        while out_sub[j].is_none() {
+            // Output j comes from the upper subnetwork, so does its input, the
+            // neighbour of that input goes through the lower subnetwork, and so
+            // the neighbour of its output has to come from the upper one again
+            out_sub[j] = Some(false);
+            in_sub[perm[j]] = false;
+            let i = perm[j] ^ 1;
+            in_sub[i] = true;
+            out_sub[inv[i]] = Some(true);
+            j = inv[i] ^ 1;
+        }
+    }
+    let mut upper = vec![0; n / 2];
+    let mut lower = vec![0; n / 2];
+    for (j, i) in perm.iter().enumerate() {
+        if out_sub[j] == Some(true) {
+            lower[j / 2] = i / 2;
+        } else {
+            upper[j / 2] = i / 2;
+        }
+    }
+    for i in 0..n / 2 {
+        settings.push(in_sub[2 * i]);
+    }
+    benes_route(&upper, settings);
+    benes_route(&lower, settings);
+    for j in 0..n / 2 {
+        settings.push(out_sub[2 * j] == Some(true));
+    }
+}
+
+// A 2x2 switch of a Benes network, 2 constraints
+fn benes_switch<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    setting: Option<bool>,
+    a: &WrappedLc,
+    b: &WrappedLc,
+) -> Result<(WrappedLc, WrappedLc), SynthesisError> {
+    let bit = AllocatedBit::alloc(cs.namespace(|| "swap"), setting)?;
+    conditional_swap(cs, &Boolean::Is(bit), a, b)
+}
+
+// Permute vals through a Benes network, vals.len() must be a power of two
+fn benes_network<CS: ConstraintSystem<BellmanFr>, I: Iterator<Item = Option<bool>>>(
+    cs: &mut CS,
+    vals: &[WrappedLc],
+    settings: &mut I,
+) -> Result<Vec<WrappedLc>, SynthesisError> {
+    let n = vals.len();
+    if n <= 1 {
+        return Ok(vals.to_vec());
+    }
+    if n == 2 {
+        let (a, b) = benes_switch(
+            &mut cs.namespace(|| "switch"),
+            settings.next().unwrap(),
+            &vals[0],
+            &vals[1],
+        )?;
+        return Ok(vec![a, b]);
+    }
+    let mut upper = Vec::new();
+    let mut lower = Vec::new();
+    for i in 0..n / 2 {
+        let (u, l) = benes_switch(
+            &mut cs.namespace(|| format!("in {}", i)),
+            settings.next().unwrap(),
+            &vals[2 * i],
+            &vals[2 * i + 1],
+        )?;
+        upper.push(u);
+        lower.push(l);
+    }
+    let upper = benes_network(&mut cs.namespace(|| "upper"), &upper, settings)?;
+    let lower = benes_network(&mut cs.namespace(|| "lower"), &lower, settings)?;
+    let mut result = Vec::new();
+    for i in 0..n / 2 {
+        let (a, b) = benes_switch(
+            &mut cs.namespace(|| format!("out {}", i)),
+            settings.next().unwrap(),
+            &upper[i],
+            &lower[i],
+        )?;
+        result.push(a);
+        result.push(b);
+    }
+    Ok(result)
+}
+
+// Assert b is a permutation of a, by routing a through a Benes network
+// Both are padded with zeros to the next power of two n, which costs
+// ~n * (2 * log2(n) - 1) + n constraints
+pub fn assert_permutation<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: &[WrappedLc],
+    b: &[WrappedLc],
+) -> Result<(), SynthesisError> {
+    assert_eq!(a.len(), b.len());
+    let n = a.len().next_power_of_two();
+    let mut a = a.to_vec();
+    let mut b = b.to_vec();
+    a.resize(n, WrappedLc::zero());
+    b.resize(n, WrappedLc::zero());
+
+    // Match every output with an unused input of the same value
+    let a_vals = a.iter().map(|v| v.get_value()).collect::<Option<Vec<_>>>();
+    let b_vals = b.iter().map(|v| v.get_value()).collect::<Option<Vec<_>>>();
+    let settings = a_vals.zip(b_vals).map(|(a_vals, b_vals)| {
+        let mut used = vec![false; n];
+        let mut perm = Vec::new();
+        for b_val in b_vals.iter() {
+            match (0..n).find(|i| !used[*i] && a_vals[*i] == *b_val) {
+                Some(i) => {
+                    used[i] = true;
+                    perm.push(i);
+                }
+                None => {
+                    // Not a permutation, any routing will be rejected
+                    perm = (0..n).collect();
+                    break;
+                }
+            }
+        }
+        let mut settings = Vec::new();
+        benes_route(&perm, &mut settings);
+        settings
+    });
+    // Settings are unknown while generating parameters
+    let mut settings_iter = settings
+        .unwrap_or_default()
+        .into_iter()
+        .map(Some)
+        .chain(std::iter::repeat(None));
+
+    let out = benes_network(&mut cs.namespace(|| "network"), &a, &mut settings_iter)?;
+    for (i, (out, b)) in out.iter().zip(b.iter()).enumerate() {
+        cs.enforce(
+            || format!("out {} == b {}", i, i),
+            |lc| lc + &out.0 - &b.0,
+            |lc| lc + CS::one(),
+            |lc| lc,
+        );
+    }
+    Ok(())
+}
+
 // Check if a number is zero, 2 constraints
 pub fn is_zero<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
@@ -109,8 +481,9 @@ pub fn is_zero<CS: ConstraintSystem<BellmanFr>>(
         |lc| lc + inv.get_variable(),
         |lc| lc + out.get_variable() - CS::one(),
     );
+    // Must not reuse the "calc out" name, TestConstraintSystem rejects duplicate paths
     cs.enforce(
-        || "calc out",
+        || "out * a == 0",
         |lc| lc + out.get_variable(),
         |lc| lc + a.get_variable(),
         |lc| lc,
@@ -118,22 +491,56 @@ pub fn is_zero<CS: ConstraintSystem<BellmanFr>>(
     Ok(out)
 }
 
+// 1 / a, 1 constraint
+// Fails with DivisionByZero when a is zero
+pub fn inverse<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    let inv = AllocatedNum::alloc(&mut *cs, || {
+        let a = a.get_value().ok_or(SynthesisError::AssignmentMissing)?;
+        Option::from(a.invert()).ok_or(SynthesisError::DivisionByZero)
+    })?;
+    cs.enforce(
+        || "a * inv == 1",
+        |lc| lc + a.get_variable(),
+        |lc| lc + inv.get_variable(),
+        |lc| lc + CS::one(),
+    );
+    Ok(inv)
+}
+
+// a / b, 2 constraints
+// Fails with DivisionByZero when b is zero (Even if a is zero too)
+pub fn div<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    let b_inv = inverse(&mut cs.namespace(|| "b_inv"), b)?;
+    a.mul(cs.namespace(|| "a * b_inv"), &b_inv)
+}
+
+// Assert a != 0, 1 constraint
+// Fails with DivisionByZero when a is zero
+pub fn assert_nonzero<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    inverse(cs, a)?;
+    Ok(())
+}
+
 // Check a == b, two constraints
 pub fn is_equal<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
     a: AllocatedNum<BellmanFr>,
     b: AllocatedNum<BellmanFr>,
 ) -> Result<AllocatedBit, SynthesisError> {
-    let out = AllocatedBit::alloc(&mut *cs, a.get_value().map(|a| a.is_zero().into()))?;
+    let diff = a.get_value().zip(b.get_value()).map(|(a, b)| a - b);
+    let out = AllocatedBit::alloc(&mut *cs, diff.map(|d| d.is_zero().into()))?;
     let inv = AllocatedNum::alloc(&mut *cs, || {
-        a.get_value()
-            .map(|a| {
-                if a.is_zero().into() {
-                    BellmanFr::zero()
-                } else {
-                    a.invert().unwrap()
-                }
-            })
+        diff.map(|d| d.invert().unwrap_or(BellmanFr::zero()))
             .ok_or(SynthesisError::AssignmentMissing)
     })?;
     cs.enforce(
@@ -143,7 +550,7 @@ pub fn is_equal<CS: ConstraintSystem<BellmanFr>>(
         |lc| lc + out.get_variable() - CS::one(),
     );
     cs.enforce(
-        || "calc out",
+        || "out * (a - b) == 0",
         |lc| lc + out.get_variable(),
         |lc| lc + a.get_variable() - b.get_variable(),
         |lc| lc,
@@ -181,31 +588,45 @@ pub fn from_bits<CS: ConstraintSystem<BellmanFr>>(
     Ok(sum)
 }
 
-// Convert number to binary repr, bits + 1 constraints
-pub fn to_bits<CS: ConstraintSystem<BellmanFr>>(
+// Same as from_bits on little-endian Booleans, constant bits cost nothing
+// 1 constraint
+pub fn from_booleans<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
-    a: AllocatedNum<BellmanFr>,
-    num_bits: usize,
-) -> Result<Vec<AllocatedBit>, SynthesisError> {
-    let mut result = Vec::new();
+    bits: &[Boolean],
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    let sum = AllocatedNum::alloc(&mut *cs, || {
+        let mut result = BellmanFr::zero();
+        let mut coeff = BellmanFr::one();
+        for bit in bits.iter() {
+            if bit.get_value().ok_or(SynthesisError::AssignmentMissing)? {
+                result.add_assign(&coeff);
+            }
+            coeff = coeff.double();
+        }
+        Ok(result)
+    })?;
     let mut coeff = BellmanFr::one();
     let mut all = LinearCombination::<BellmanFr>::zero();
-    let bits: Option<Vec<bool>> = a
-        .get_value()
-        .map(|v| v.to_le_bits().iter().map(|b| *b).collect());
-    for i in 0..num_bits {
-        let bit = AllocatedBit::alloc(&mut *cs, bits.as_ref().map(|b| b[i]))?;
-        all = all + (coeff, bit.get_variable());
-        result.push(bit);
+    for bit in bits.iter() {
+        all = all + &bit.lc(CS::one(), coeff);
         coeff = coeff.double();
     }
     cs.enforce(
-        || "check",
+        || "sum check",
         |lc| lc + &all,
         |lc| lc + CS::one(),
-        |lc| lc + a.get_variable(),
+        |lc| lc + sum.get_variable(),
     );
-    Ok(result)
+    Ok(sum)
+}
+
+// Convert number to binary repr, bits + 1 constraints
+pub fn to_bits<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+) -> Result<Vec<AllocatedBit>, SynthesisError> {
+    lc_to_bits(cs, &WrappedLc::alloc_num(a), num_bits)
 }
 
 // Convert number to binary repr and negate
@@ -222,12 +643,15 @@ pub fn to_bits_neg<CS: ConstraintSystem<BellmanFr>>(
         .get_value()
         .map(|v| (two_bits - v).to_le_bits().iter().map(|b| *b).collect());
     for i in 0..num_bits {
-        let bit = AllocatedBit::alloc(&mut *cs, bits.as_ref().map(|b| b[i]))?;
+        let bit = AllocatedBit::alloc(
+            &mut cs.namespace(|| format!("bit {}", i)),
+            bits.as_ref().map(|b| b[i]),
+        )?;
         all = all + (coeff, bit.get_variable());
         result.push(bit);
         coeff = coeff.double();
     }
-    let is_zero = is_zero(&mut *cs, a.clone())?;
+    let is_zero = is_zero(&mut cs.namespace(|| "is zero"), a.clone())?;
     all = all + (two_bits, is_zero.get_variable());
     cs.enforce(
         || "neg check",
@@ -274,52 +698,1378 @@ pub fn sum_bits<CS: ConstraintSystem<BellmanFr>>(
     Ok(sum)
 }
 
-// ~200 constraints
-pub fn lte<CS: ConstraintSystem<BellmanFr>>(
+// Decompose the value of a linear combination into num_bits bits, num_bits + 1 constraints
+// Unsatisfiable when the value doesn't fit in num_bits bits
+pub(crate) fn lc_to_bits<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: &WrappedLc,
+    num_bits: usize,
+) -> Result<Vec<AllocatedBit>, SynthesisError> {
+    let mut result = Vec::new();
+    let mut coeff = BellmanFr::one();
+    let mut all = LinearCombination::<BellmanFr>::zero();
+    let bits: Option<Vec<bool>> = a
+        .get_value()
+        .map(|v| v.to_le_bits().iter().map(|b| *b).collect());
+    for i in 0..num_bits {
+        let bit = AllocatedBit::alloc(
+            cs.namespace(|| format!("bit {}", i)),
+            bits.as_ref().map(|b| b[i]),
+        )?;
+        all = all + (coeff, bit.get_variable());
+        result.push(bit);
+        coeff = coeff.double();
+    }
+    cs.enforce(
+        || "check",
+        |lc| lc + &all,
+        |lc| lc + CS::one(),
+        |lc| lc + &a.0,
+    );
+    Ok(result)
+}
+
+// Assert a fits in num_bits bits, num_bits constraints
+// The highest bit is never allocated: a minus its lower bits must be either zero
+// or 2^(num_bits - 1), which also takes care of the recomposition check
+// There is no batched form, packing can't go below a constraint per checked bit
+// in R1CS (Each bit needs its own booleanity check), it would only add the cost
+// of linking every value back to its bits
+pub fn range_check<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: &WrappedLc,
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    assert!(num_bits < BellmanFr::CAPACITY as usize);
+    if num_bits == 0 {
+        cs.enforce(|| "a == 0", |lc| lc + &a.0, |lc| lc + CS::one(), |lc| lc);
+        return Ok(());
+    }
+    let bits: Option<Vec<bool>> = a
+        .get_value()
+        .map(|v| v.to_le_bits().iter().map(|b| *b).collect());
+    let mut coeff = BellmanFr::one();
+    let mut rest = a.0.clone();
+    for i in 0..num_bits - 1 {
+        let bit = AllocatedBit::alloc(
+            cs.namespace(|| format!("bit {}", i)),
+            bits.as_ref().map(|b| b[i]),
+        )?;
+        rest = rest - (coeff, bit.get_variable());
+        coeff = coeff.double();
+    }
+    cs.enforce(
+        || "rest * (2^(num_bits - 1) - rest) == 0",
+        |lc| lc + &rest,
+        |lc| lc + (coeff, CS::one()) - &rest,
+        |lc| lc,
+    );
+    Ok(())
+}
+
+// a - b + c
+fn diff_plus_constant<CS: ConstraintSystem<BellmanFr>>(
+    a: &AllocatedNum<BellmanFr>,
+    b: &AllocatedNum<BellmanFr>,
+    c: BellmanFr,
+) -> WrappedLc {
+    let mut diff = WrappedLc::alloc_num(a.clone()) - WrappedLc::alloc_num(b.clone());
+    diff.add_constant::<CS>(c);
+    diff
+}
+
+// Check a >= b (Or a > b when strict), where a and b are num_bits-bit numbers
+// ~3 * num_bits constraints, unsatisfiable when a or b doesn't fit in num_bits bits
+fn compare<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
     a: AllocatedNum<BellmanFr>,
     b: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+    strict: bool,
 ) -> Result<AllocatedBit, SynthesisError> {
-    let a = to_bits(&mut *cs, a, 64)?;
-    let b_neg = to_bits_neg(&mut *cs, b, 64)?;
-    let c = sum_bits(&mut *cs, a, b_neg)?;
-    let c_bits = to_bits(&mut *cs, c, 65)?;
-    Ok(c_bits[63].clone())
+    assert!(num_bits < BellmanFr::CAPACITY as usize);
+    range_check(
+        &mut cs.namespace(|| "val 0"),
+        &WrappedLc::alloc_num(a.clone()),
+        num_bits,
+    )?;
+    range_check(
+        &mut cs.namespace(|| "val 1"),
+        &WrappedLc::alloc_num(b.clone()),
+        num_bits,
+    )?;
+    // a - b + 2^num_bits - strict is in [0, 2^(num_bits + 1)), its highest bit is
+    // set iff the comparison holds
+    let two_bits = BellmanFr::from(2).pow_vartime(&[num_bits as u64, 0, 0, 0]);
+    let diff = diff_plus_constant::<CS>(&a, &b, two_bits - BellmanFr::from(strict as u64));
+    let mut diff_bits = lc_to_bits(&mut cs.namespace(|| "diff"), &diff, num_bits + 1)?;
+    Ok(diff_bits.pop().unwrap())
 }
 
-pub fn assert_equal<CS: ConstraintSystem<BellmanFr>>(
+// Assert a >= b (Or a > b when strict), where a and b are num_bits-bit numbers
+// ~3 * num_bits constraints
+fn assert_compare<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
-    enabled: AllocatedBit,
     a: AllocatedNum<BellmanFr>,
     b: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+    strict: bool,
 ) -> Result<(), SynthesisError> {
-    let enabled_value = enabled.get_value();
-    let enabled_in_a = cs.alloc(
-        || "",
-        || {
-            enabled_value
-                .map(|e| {
-                    if e {
-                        a.get_value()
-                    } else {
-                        Some(BellmanFr::zero())
-                    }
-                })
-                .unwrap_or(None)
-                .ok_or(SynthesisError::AssignmentMissing)
-        },
+    assert!(num_bits < BellmanFr::CAPACITY as usize);
+    range_check(
+        &mut cs.namespace(|| "val 0"),
+        &WrappedLc::alloc_num(a.clone()),
+        num_bits,
     )?;
-    cs.enforce(
-        || "enabled * a == enabled_in_a",
-        |lc| lc + enabled.get_variable(),
-        |lc| lc + a.get_variable(),
-        |lc| lc + enabled_in_a,
-    );
-    cs.enforce(
-        || "enabled * b == enabled_in_a",
-        |lc| lc + enabled.get_variable(),
-        |lc| lc + b.get_variable(),
-        |lc| lc + enabled_in_a,
-    );
-    Ok(())
+    range_check(
+        &mut cs.namespace(|| "val 1"),
+        &WrappedLc::alloc_num(b.clone()),
+        num_bits,
+    )?;
+    assert_compare_unchecked(&mut cs.namespace(|| "diff"), &a, &b, num_bits, strict)
+}
+
+// Same as assert_compare, for a and b already known to fit in num_bits bits,
+// num_bits constraints
+fn assert_compare_unchecked<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: &AllocatedNum<BellmanFr>,
+    b: &AllocatedNum<BellmanFr>,
+    num_bits: usize,
+    strict: bool,
+) -> Result<(), SynthesisError> {
+    // a - b - strict is in [0, 2^num_bits) iff the comparison holds
+    let diff = diff_plus_constant::<CS>(a, b, -BellmanFr::from(strict as u64));
+    range_check(cs, &diff, num_bits)
+}
+
+// Check a < b, where a and b are num_bits-bit numbers
+pub fn lt<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+) -> Result<AllocatedBit, SynthesisError> {
+    compare(cs, b, a, num_bits, true)
+}
+
+// Check a <= b, where a and b are num_bits-bit numbers
+pub fn lte_bits<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+) -> Result<AllocatedBit, SynthesisError> {
+    compare(cs, b, a, num_bits, false)
+}
+
+// Check a <= b, where a and b are 64-bit numbers
+pub fn lte<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+) -> Result<AllocatedBit, SynthesisError> {
+    lte_bits(cs, a, b, 64)
+}
+
+// Check a > b, where a and b are num_bits-bit numbers
+pub fn gt<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+) -> Result<AllocatedBit, SynthesisError> {
+    compare(cs, a, b, num_bits, true)
+}
+
+// Check a >= b, where a and b are num_bits-bit numbers
+pub fn gte<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+) -> Result<AllocatedBit, SynthesisError> {
+    compare(cs, a, b, num_bits, false)
+}
+
+pub fn assert_lt<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    assert_compare(cs, b, a, num_bits, true)
+}
+
+pub fn assert_lte<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    assert_compare(cs, b, a, num_bits, false)
+}
+
+pub fn assert_gt<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    assert_compare(cs, a, b, num_bits, true)
+}
+
+pub fn assert_gte<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    assert_compare(cs, a, b, num_bits, false)
+}
+
+// Assert vals[i] <= vals[i + 1] (Or vals[i] < vals[i + 1] when strict), where all
+// values are num_bits-bit numbers, ~2 * num_bits constraints per value
+fn assert_sorted_impl<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    vals: &[AllocatedNum<BellmanFr>],
+    num_bits: usize,
+    strict: bool,
+) -> Result<(), SynthesisError> {
+    assert!(num_bits < BellmanFr::CAPACITY as usize);
+    // Each value is range-checked once, and then only the gaps between neighbours
+    for (i, val) in vals.iter().enumerate() {
+        range_check(
+            &mut cs.namespace(|| format!("val {}", i)),
+            &WrappedLc::alloc_num(val.clone()),
+            num_bits,
+        )?;
+    }
+    for (i, pair) in vals.windows(2).enumerate() {
+        assert_compare_unchecked(
+            &mut cs.namespace(|| format!("pair {}", i)),
+            &pair[1],
+            &pair[0],
+            num_bits,
+            strict,
+        )?;
+    }
+    Ok(())
+}
+
+// Assert vals is sorted in non-decreasing order
+pub fn assert_sorted<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    vals: &[AllocatedNum<BellmanFr>],
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    assert_sorted_impl(cs, vals, num_bits, false)
+}
+
+// Assert vals is sorted in increasing order, which also means there are no duplicates
+pub fn assert_strictly_sorted<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    vals: &[AllocatedNum<BellmanFr>],
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    assert_sorted_impl(cs, vals, num_bits, true)
+}
+
+// Assert the num_bits-bit values are pairwise distinct, in any order
+// A sorted copy is provided as witness, proven to be a permutation of vals and
+// to be strictly sorted, which costs O(n * (log(n) + num_bits)) rather than
+// comparing every pair
+pub fn assert_unique<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    vals: &[AllocatedNum<BellmanFr>],
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    let sorted_vals = vals
+        .iter()
+        .map(|v| v.get_value())
+        .collect::<Option<Vec<_>>>()
+        .map(|mut vals| {
+            // Compare as big-endian integers
+            vals.sort_by_key(|v| {
+                let mut repr = v.to_repr();
+                repr.reverse();
+                repr
+            });
+            vals
+        });
+    let mut sorted = Vec::new();
+    for i in 0..vals.len() {
+        sorted.push(AllocatedNum::alloc(
+            cs.namespace(|| format!("sorted {}", i)),
+            || {
+                sorted_vals
+                    .as_ref()
+                    .map(|v| v[i])
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+        )?);
+    }
+    assert_permutation(
+        &mut cs.namespace(|| "permutation"),
+        &vals
+            .iter()
+            .map(|v| WrappedLc::alloc_num(v.clone()))
+            .collect::<Vec<_>>(),
+        &sorted
+            .iter()
+            .map(|v| WrappedLc::alloc_num(v.clone()))
+            .collect::<Vec<_>>(),
+    )?;
+    assert_strictly_sorted(&mut cs.namespace(|| "sorted"), &sorted, num_bits)
+}
+
+pub fn assert_equal<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    let enabled_value = enabled.get_value();
+    let enabled_in_a = cs.alloc(
+        || "",
+        || {
+            enabled_value
+                .map(|e| {
+                    if e {
+                        a.get_value()
+                    } else {
+                        Some(BellmanFr::zero())
+                    }
+                })
+                .unwrap_or(None)
+                .ok_or(SynthesisError::AssignmentMissing)
+        },
+    )?;
+    cs.enforce(
+        || "enabled * a == enabled_in_a",
+        |lc| lc + enabled.get_variable(),
+        |lc| lc + a.get_variable(),
+        |lc| lc + enabled_in_a,
+    );
+    cs.enforce(
+        || "enabled * b == enabled_in_a",
+        |lc| lc + enabled.get_variable(),
+        |lc| lc + b.get_variable(),
+        |lc| lc + enabled_in_a,
+    );
+    Ok(())
+}
+
+// Bitwise a ^ b, a.len() constraints
+pub fn xor_bits<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: &[AllocatedBit],
+    b: &[AllocatedBit],
+) -> Result<Vec<AllocatedBit>, SynthesisError> {
+    assert_eq!(a.len(), b.len());
+    a.iter()
+        .zip(b.iter())
+        .enumerate()
+        .map(|(i, (a, b))| AllocatedBit::xor(cs.namespace(|| format!("bit {}", i)), a, b))
+        .collect()
+}
+
+// Bitwise a & b, a.len() constraints
+pub fn and_bits<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: &[AllocatedBit],
+    b: &[AllocatedBit],
+) -> Result<Vec<AllocatedBit>, SynthesisError> {
+    assert_eq!(a.len(), b.len());
+    a.iter()
+        .zip(b.iter())
+        .enumerate()
+        .map(|(i, (a, b))| AllocatedBit::and(cs.namespace(|| format!("bit {}", i)), a, b))
+        .collect()
+}
+
+// a | b, 2 constraints
+fn or_bit<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: &AllocatedBit,
+    b: &AllocatedBit,
+) -> Result<AllocatedBit, SynthesisError> {
+    let out = AllocatedBit::alloc(
+        &mut *cs,
+        a.get_value().zip(b.get_value()).map(|(a, b)| a | b),
+    )?;
+    cs.enforce(
+        || "(1 - a) * (1 - b) == 1 - out",
+        |lc| lc + CS::one() - a.get_variable(),
+        |lc| lc + CS::one() - b.get_variable(),
+        |lc| lc + CS::one() - out.get_variable(),
+    );
+    Ok(out)
+}
+
+// Bitwise a | b, 2 * a.len() constraints
+pub fn or_bits<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: &[AllocatedBit],
+    b: &[AllocatedBit],
+) -> Result<Vec<AllocatedBit>, SynthesisError> {
+    assert_eq!(a.len(), b.len());
+    a.iter()
+        .zip(b.iter())
+        .enumerate()
+        .map(|(i, (a, b))| or_bit(&mut cs.namespace(|| format!("bit {}", i)), a, b))
+        .collect()
+}
+
+// Number of set bits, 1 constraint
+pub fn popcount<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    bits: &[AllocatedBit],
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    let count = AllocatedNum::alloc(&mut *cs, || {
+        let mut result = BellmanFr::zero();
+        for bit in bits.iter() {
+            if bit.get_value().ok_or(SynthesisError::AssignmentMissing)? {
+                result.add_assign(&BellmanFr::one());
+            }
+        }
+        Ok(result)
+    })?;
+    let mut all = LinearCombination::<BellmanFr>::zero();
+    for bit in bits.iter() {
+        all = all + bit.get_variable();
+    }
+    cs.enforce(
+        || "count check",
+        |lc| lc + &all,
+        |lc| lc + CS::one(),
+        |lc| lc + count.get_variable(),
+    );
+    Ok(count)
+}
+
+// Bits are little-endian (Like to_bits/from_bits), so shifting left means
+// multiplying by 2^n, the width is preserved and shifted-out bits are dropped
+// Shifted-in bits are constant zeros, so no constraints (Pack with from_booleans)
+pub fn shift_left(bits: &[AllocatedBit], n: usize) -> Vec<Boolean> {
+    let n = std::cmp::min(n, bits.len());
+    let mut result = vec![Boolean::constant(false); n];
+    result.extend(bits[..bits.len() - n].iter().cloned().map(Boolean::Is));
+    result
+}
+
+// Dividing by 2^n, no constraints
+pub fn shift_right(bits: &[AllocatedBit], n: usize) -> Vec<Boolean> {
+    let n = std::cmp::min(n, bits.len());
+    let mut result = bits[n..]
+        .iter()
+        .cloned()
+        .map(Boolean::Is)
+        .collect::<Vec<_>>();
+    result.extend(vec![Boolean::constant(false); n]);
+    result
+}
+
+// Rotations are just rewirings, no constraints
+pub fn rotate_left(bits: &[AllocatedBit], n: usize) -> Vec<AllocatedBit> {
+    let mut result = bits.to_vec();
+    if !result.is_empty() {
+        result.rotate_right(n % bits.len());
+    }
+    result
+}
+
+pub fn rotate_right(bits: &[AllocatedBit], n: usize) -> Vec<AllocatedBit> {
+    let mut result = bits.to_vec();
+    if !result.is_empty() {
+        result.rotate_left(n % bits.len());
+    }
+    result
+}
+
+// Check if all of the bits have the same value, 4 constraints
+// popcount * (len - popcount) is zero iff all bits are zero or all bits are one
+pub fn all_bits_equal<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    bits: &[AllocatedBit],
+) -> Result<AllocatedBit, SynthesisError> {
+    let mut count = WrappedLc::zero();
+    for bit in bits.iter() {
+        count = count
+            + WrappedLc(
+                LinearCombination::<BellmanFr>::zero() + bit.get_variable(),
+                bit.get_value().map(|b| {
+                    if b {
+                        BellmanFr::one()
+                    } else {
+                        BellmanFr::zero()
+                    }
+                }),
+            );
+    }
+    let mut rest = -count.clone();
+    rest.add_constant::<CS>(BellmanFr::from(bits.len() as u64));
+    let prod = count.mul(&mut cs.namespace(|| "count * rest"), &rest)?;
+    is_zero(&mut cs.namespace(|| "is zero"), prod)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bellman::gadgets::test::TestConstraintSystem;
+
+    fn alloc_num<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        val: BellmanFr,
+    ) -> AllocatedNum<BellmanFr> {
+        AllocatedNum::alloc(cs, || Ok(val)).unwrap()
+    }
+
+    fn alloc_select<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        variant: usize,
+        val: bool,
+    ) -> Boolean {
+        match variant {
+            0 => Boolean::Is(AllocatedBit::alloc(cs, Some(val)).unwrap()),
+            1 => Boolean::Not(AllocatedBit::alloc(cs, Some(!val)).unwrap()),
+            _ => Boolean::Constant(val),
+        }
+    }
+
+    fn fr_from_u128(val: u128) -> BellmanFr {
+        BellmanFr::from_raw([val as u64, (val >> 64) as u64, 0, 0])
+    }
+
+    #[test]
+    fn test_mux() {
+        for variant in 0..3 {
+            for s in [false, true] {
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let select = alloc_select(&mut cs.namespace(|| "s"), variant, s);
+                let a = alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(123));
+                let b = alloc_num(&mut cs.namespace(|| "b"), BellmanFr::from(234));
+                let ret = mux(
+                    &mut cs.namespace(|| "mux"),
+                    &select,
+                    &WrappedLc::alloc_num(a),
+                    &WrappedLc::alloc_num(b),
+                )
+                .unwrap();
+                let expected = if s { 234 } else { 123 };
+                assert_eq!(ret.get_value(), Some(BellmanFr::from(expected)));
+                assert!(cs.is_satisfied());
+
+                // A prover can't claim the other branch
+                cs.set("mux/num", BellmanFr::from(if s { 123 } else { 234 }));
+                assert!(!cs.is_satisfied());
+            }
+        }
+    }
+
+    #[test]
+    fn test_mux_constant_selector_with_constants() {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let ret = mux(
+            &mut cs.namespace(|| "mux"),
+            &Boolean::Constant(true),
+            &WrappedLc::zero(),
+            &WrappedLc::constant::<TestConstraintSystem<BellmanFr>>(BellmanFr::from(5)),
+        )
+        .unwrap();
+        assert_eq!(ret.get_value(), Some(BellmanFr::from(5)));
+        assert!(cs.is_satisfied());
+    }
+
+    type CompareGadget = fn(
+        &mut TestConstraintSystem<BellmanFr>,
+        AllocatedNum<BellmanFr>,
+        AllocatedNum<BellmanFr>,
+        usize,
+    ) -> Result<AllocatedBit, SynthesisError>;
+    type AssertCompareGadget = fn(
+        &mut TestConstraintSystem<BellmanFr>,
+        AllocatedNum<BellmanFr>,
+        AllocatedNum<BellmanFr>,
+        usize,
+    ) -> Result<(), SynthesisError>;
+
+    type NativeCompare = fn(u128, u128) -> bool;
+
+    fn comparisons() -> Vec<(CompareGadget, AssertCompareGadget, NativeCompare)> {
+        vec![
+            (lt, assert_lt, |a, b| a < b),
+            (lte_bits, assert_lte, |a, b| a <= b),
+            (gt, assert_gt, |a, b| a > b),
+            (gte, assert_gte, |a, b| a >= b),
+        ]
+    }
+
+    #[test]
+    fn test_comparisons() {
+        for num_bits in [1, 16, 64, 128] {
+            let max = u128::MAX >> (128 - num_bits);
+            let vals = [0, 1, max / 2, max - 1, max];
+            for (cmp, assert_cmp, expected) in comparisons() {
+                for a in vals {
+                    for b in vals {
+                        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                        let a_num = alloc_num(&mut cs.namespace(|| "a"), fr_from_u128(a));
+                        let b_num = alloc_num(&mut cs.namespace(|| "b"), fr_from_u128(b));
+                        let out = cmp(&mut cs, a_num.clone(), b_num.clone(), num_bits).unwrap();
+                        assert_eq!(out.get_value(), Some(expected(a, b)));
+                        assert!(cs.is_satisfied());
+
+                        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                        let a_num = alloc_num(&mut cs.namespace(|| "a"), fr_from_u128(a));
+                        let b_num = alloc_num(&mut cs.namespace(|| "b"), fr_from_u128(b));
+                        assert_cmp(&mut cs, a_num, b_num, num_bits).unwrap();
+                        assert_eq!(cs.is_satisfied(), expected(a, b));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_comparisons_reject_wide_inputs() {
+        let too_big = BellmanFr::from(1 << 16);
+        let neg_one = -BellmanFr::one();
+        for (cmp, assert_cmp, _) in comparisons() {
+            for (a, b) in [
+                (too_big, BellmanFr::zero()),
+                (BellmanFr::zero(), too_big),
+                (neg_one, BellmanFr::one()),
+                (BellmanFr::one(), neg_one),
+            ] {
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let a_num = alloc_num(&mut cs.namespace(|| "a"), a);
+                let b_num = alloc_num(&mut cs.namespace(|| "b"), b);
+                cmp(&mut cs, a_num, b_num, 16).unwrap();
+                assert!(!cs.is_satisfied());
+
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let a_num = alloc_num(&mut cs.namespace(|| "a"), a);
+                let b_num = alloc_num(&mut cs.namespace(|| "b"), b);
+                assert_cmp(&mut cs, a_num, b_num, 16).unwrap();
+                assert!(!cs.is_satisfied());
+            }
+        }
+    }
+
+    #[test]
+    fn test_range_check() {
+        for num_bits in [0, 1, 8, 64, 128] {
+            let max = if num_bits == 0 {
+                0
+            } else {
+                u128::MAX >> (128 - num_bits)
+            };
+            for (val, fits) in [
+                (BellmanFr::zero(), true),
+                (fr_from_u128(max), true),
+                (fr_from_u128(max) + BellmanFr::one(), false),
+                (-BellmanFr::one(), false),
+            ] {
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let a = alloc_num(&mut cs.namespace(|| "a"), val);
+                range_check(
+                    &mut cs.namespace(|| "range"),
+                    &WrappedLc::alloc_num(a),
+                    num_bits,
+                )
+                .unwrap();
+                assert_eq!(cs.is_satisfied(), fits);
+                assert_eq!(cs.num_constraints(), std::cmp::max(num_bits, 1));
+            }
+        }
+    }
+
+    // Make sure the linear combination evaluates to its witness value
+    fn enforce_consistent<CS: ConstraintSystem<BellmanFr>>(cs: &mut CS, a: &WrappedLc) {
+        let num = AllocatedNum::alloc(&mut *cs, || Ok(a.get_value().unwrap())).unwrap();
+        cs.enforce(
+            || "a == num",
+            |lc| lc + &a.0,
+            |lc| lc + CS::one(),
+            |lc| lc + num.get_variable(),
+        );
+    }
+
+    #[test]
+    fn test_wrapped_lc_arithmetic() {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a = WrappedLc::alloc_num(alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(3)));
+        let b = WrappedLc::alloc_num(alloc_num(&mut cs.namespace(|| "b"), BellmanFr::from(5)));
+        let c = WrappedLc::constant::<TestConstraintSystem<BellmanFr>>(BellmanFr::from(7));
+
+        let sum = a.clone() + &b + c.clone();
+        assert_eq!(sum.get_value(), Some(BellmanFr::from(15)));
+        enforce_consistent(&mut cs.namespace(|| "sum"), &sum);
+
+        let diff = a.clone() - b.clone() - &c;
+        assert_eq!(diff.get_value(), Some(-BellmanFr::from(9)));
+        enforce_consistent(&mut cs.namespace(|| "diff"), &diff);
+
+        let scaled = (a.clone() + &c).scale(BellmanFr::from(3));
+        assert_eq!(scaled.get_value(), Some(BellmanFr::from(30)));
+        enforce_consistent(&mut cs.namespace(|| "scaled"), &scaled);
+
+        let neg = -(b.clone() - &c);
+        assert_eq!(neg.get_value(), Some(BellmanFr::from(2)));
+        enforce_consistent(&mut cs.namespace(|| "neg"), &neg);
+
+        let prod = sum.mul(&mut cs.namespace(|| "prod"), &diff).unwrap();
+        assert_eq!(prod.get_value(), Some(-BellmanFr::from(135)));
+        assert!(cs.is_satisfied());
+
+        cs.set("prod/num", BellmanFr::from(135));
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_wrapped_lc_missing_values() {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a = WrappedLc::alloc_num(alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(3)));
+        let unknown = WrappedLc(LinearCombination::zero(), None);
+        assert_eq!((a.clone() + &unknown).get_value(), None);
+        assert_eq!((a.clone() - &unknown).get_value(), None);
+        assert_eq!((-unknown.clone()).get_value(), None);
+        assert!(matches!(
+            a.mul(&mut cs.namespace(|| "prod"), &unknown),
+            Err(SynthesisError::AssignmentMissing)
+        ));
+    }
+
+    fn alloc_bits<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        val: u64,
+        num_bits: usize,
+    ) -> Vec<AllocatedBit> {
+        let num = alloc_num(&mut cs.namespace(|| "num"), BellmanFr::from(val));
+        to_bits(&mut cs.namespace(|| "bits"), num, num_bits).unwrap()
+    }
+
+    fn bits_value(bits: &[AllocatedBit]) -> u64 {
+        bits.iter()
+            .enumerate()
+            .map(|(i, b)| (b.get_value().unwrap() as u64) << i)
+            .sum()
+    }
+
+    const BIT_VECTORS: [u64; 6] = [0, 1, 0x8000, 0xffff, 0x1234, 0xa5c3];
+
+    #[test]
+    fn test_bitwise_ops() {
+        type BitwiseGadget = fn(
+            &mut TestConstraintSystem<BellmanFr>,
+            &[AllocatedBit],
+            &[AllocatedBit],
+        ) -> Result<Vec<AllocatedBit>, SynthesisError>;
+        type NativeBitwise = fn(u64, u64) -> u64;
+        let ops: [(BitwiseGadget, NativeBitwise); 3] = [
+            (xor_bits, |a, b| a ^ b),
+            (and_bits, |a, b| a & b),
+            (or_bits, |a, b| a | b),
+        ];
+        for (gadget, native) in ops {
+            for a in BIT_VECTORS {
+                for b in BIT_VECTORS {
+                    let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                    let a_bits = alloc_bits(&mut cs.namespace(|| "a"), a, 16);
+                    let b_bits = alloc_bits(&mut cs.namespace(|| "b"), b, 16);
+                    let out = gadget(&mut cs, &a_bits, &b_bits).unwrap();
+                    assert_eq!(bits_value(&out), native(a, b));
+                    let out_num = from_bits(&mut cs.namespace(|| "out"), out).unwrap();
+                    assert_eq!(out_num.get_value(), Some(BellmanFr::from(native(a, b))));
+                    assert!(cs.is_satisfied());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_or_bits_rejects_wrong_output() {
+        for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let a = AllocatedBit::alloc(cs.namespace(|| "a"), Some(a)).unwrap();
+            let b = AllocatedBit::alloc(cs.namespace(|| "b"), Some(b)).unwrap();
+            let out = or_bits(&mut cs.namespace(|| "or"), &[a], &[b]).unwrap();
+            assert!(cs.is_satisfied());
+            let flipped = if out[0].get_value().unwrap() {
+                BellmanFr::zero()
+            } else {
+                BellmanFr::one()
+            };
+            cs.set("or/bit 0/boolean", flipped);
+            assert!(!cs.is_satisfied());
+        }
+    }
+
+    #[test]
+    fn test_popcount() {
+        for a in BIT_VECTORS {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let bits = alloc_bits(&mut cs.namespace(|| "a"), a, 16);
+            let count = popcount(&mut cs.namespace(|| "popcount"), &bits).unwrap();
+            assert_eq!(
+                count.get_value(),
+                Some(BellmanFr::from(a.count_ones() as u64))
+            );
+            assert!(cs.is_satisfied());
+
+            cs.set("popcount/num", BellmanFr::from(a.count_ones() as u64 + 1));
+            assert!(!cs.is_satisfied());
+        }
+    }
+
+    #[test]
+    fn test_shifts_and_rotations() {
+        for a in BIT_VECTORS {
+            for n in [0, 1, 7, 15, 16, 20] {
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let bits = alloc_bits(&mut cs.namespace(|| "a"), a, 16);
+                let shl = shift_left(&bits, n);
+                let shr = shift_right(&bits, n);
+                let before = cs.num_constraints();
+                let shl_num = from_booleans(&mut cs.namespace(|| "shl"), &shl).unwrap();
+                let shr_num = from_booleans(&mut cs.namespace(|| "shr"), &shr).unwrap();
+                // Only the packing constraints
+                assert_eq!(cs.num_constraints() - before, 2);
+                assert_eq!(
+                    shl_num.get_value(),
+                    Some(BellmanFr::from(
+                        a.checked_shl(n as u32).unwrap_or(0) & 0xffff
+                    ))
+                );
+                assert_eq!(
+                    shr_num.get_value(),
+                    Some(BellmanFr::from(a.checked_shr(n as u32).unwrap_or(0)))
+                );
+                assert_eq!(
+                    bits_value(&rotate_left(&bits, n)),
+                    (a as u16).rotate_left(n as u32) as u64
+                );
+                assert_eq!(
+                    bits_value(&rotate_right(&bits, n)),
+                    (a as u16).rotate_right(n as u32) as u64
+                );
+                assert!(cs.is_satisfied());
+                cs.set("shl/num", shl_num.get_value().unwrap() + BellmanFr::one());
+                assert!(!cs.is_satisfied());
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_bits_equal() {
+        for (a, num_bits) in [(0, 16), (0xffff, 16), (1, 1), (0, 1), (0x7fff, 16), (1, 16)] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let bits = alloc_bits(&mut cs.namespace(|| "a"), a, num_bits);
+            let eq = all_bits_equal(&mut cs.namespace(|| "eq"), &bits).unwrap();
+            let expected = a == 0 || a == (1 << num_bits) - 1;
+            assert_eq!(eq.get_value(), Some(expected));
+            assert!(cs.is_satisfied());
+
+            cs.set(
+                "eq/is zero/boolean",
+                if expected {
+                    BellmanFr::zero()
+                } else {
+                    BellmanFr::one()
+                },
+            );
+            assert!(!cs.is_satisfied());
+        }
+    }
+
+    fn alloc_index<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        index: usize,
+        num_bits: usize,
+        constant: bool,
+    ) -> Vec<Boolean> {
+        (0..num_bits)
+            .map(|i| {
+                let bit = (index >> i) & 1 == 1;
+                if constant {
+                    Boolean::Constant(bit)
+                } else {
+                    Boolean::Is(
+                        AllocatedBit::alloc(cs.namespace(|| format!("bit {}", i)), Some(bit))
+                            .unwrap(),
+                    )
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_select() {
+        for num_bits in 0..4 {
+            let size = 1 << num_bits;
+            for index in 0..size {
+                for constant in [false, true] {
+                    let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                    let bits =
+                        alloc_index(&mut cs.namespace(|| "index"), index, num_bits, constant);
+                    let table = (0..size)
+                        .map(|i| {
+                            WrappedLc::alloc_num(alloc_num(
+                                &mut cs.namespace(|| format!("entry {}", i)),
+                                BellmanFr::from(100 + i as u64),
+                            ))
+                        })
+                        .collect::<Vec<_>>();
+                    let before = cs.num_constraints();
+                    let out = select(&mut cs.namespace(|| "select"), &bits, &table).unwrap();
+                    assert_eq!(out.get_value(), Some(BellmanFr::from(100 + index as u64)));
+                    assert!(cs.is_satisfied());
+                    assert_eq!(cs.num_constraints() - before, std::cmp::max(size - 1, 1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_constant() {
+        for num_bits in 0..5 {
+            let size = 1 << num_bits;
+            let table = (0..size)
+                .map(|i| BellmanFr::from(1000 + 7 * i as u64))
+                .collect::<Vec<_>>();
+            for index in 0..size {
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let bits = alloc_index(&mut cs.namespace(|| "index"), index, num_bits, false);
+                let before = cs.num_constraints();
+                let out = select_constant(&mut cs.namespace(|| "select"), &bits, &table).unwrap();
+                assert_eq!(out.get_value(), Some(table[index]));
+                assert!(cs.is_satisfied());
+                assert_eq!(
+                    cs.num_constraints() - before,
+                    std::cmp::max(size / 2, 2) - 1
+                );
+
+                // Claiming any other entry is rejected
+                let out_path = if num_bits < 2 {
+                    "select/num"
+                } else {
+                    "select/root/num"
+                };
+                cs.set(out_path, table[(index + 1) % size] + BellmanFr::one());
+                assert!(!cs.is_satisfied());
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        for index in 0..4 {
+            for constant in [false, true] {
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let bits = alloc_index(&mut cs.namespace(|| "index"), index, 2, constant);
+                let v = WrappedLc::alloc_num(alloc_num(&mut cs.namespace(|| "v"), 7.into()));
+                let p = [1, 2, 3].map(|i| {
+                    WrappedLc::alloc_num(alloc_num(
+                        &mut cs.namespace(|| format!("p {}", i)),
+                        BellmanFr::from(i),
+                    ))
+                });
+                let before = cs.num_constraints();
+                let out = insert(
+                    &mut cs.namespace(|| "insert"),
+                    &[bits[0].clone(), bits[1].clone()],
+                    &v,
+                    &p,
+                )
+                .unwrap();
+                let mut expected = vec![1, 2, 3];
+                expected.insert(index, 7);
+                assert_eq!(
+                    out.iter().map(|o| o.get_value()).collect::<Vec<_>>(),
+                    expected
+                        .into_iter()
+                        .map(|e| Some(BellmanFr::from(e)))
+                        .collect::<Vec<_>>()
+                );
+                assert!(cs.is_satisfied());
+                assert_eq!(cs.num_constraints() - before, if constant { 3 } else { 5 });
+
+                cs.set("insert/w 1/num", BellmanFr::from(100));
+                assert!(!cs.is_satisfied());
+            }
+        }
+    }
+
+    #[test]
+    fn test_conditional_swap() {
+        for variant in 0..3 {
+            for s in [false, true] {
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let swap = alloc_select(&mut cs.namespace(|| "s"), variant, s);
+                let a = alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(123));
+                let b = alloc_num(&mut cs.namespace(|| "b"), BellmanFr::from(234));
+                let (x, y) = conditional_swap(
+                    &mut cs.namespace(|| "swap"),
+                    &swap,
+                    &WrappedLc::alloc_num(a),
+                    &WrappedLc::alloc_num(b),
+                )
+                .unwrap();
+                let (expected_x, expected_y) = if s { (234, 123) } else { (123, 234) };
+                assert_eq!(x.get_value(), Some(BellmanFr::from(expected_x)));
+                assert_eq!(y.get_value(), Some(BellmanFr::from(expected_y)));
+                enforce_consistent(&mut cs.namespace(|| "x"), &x);
+                enforce_consistent(&mut cs.namespace(|| "y"), &y);
+                assert!(cs.is_satisfied());
+
+                if variant != 2 {
+                    cs.set("swap/num", BellmanFr::from(1000));
+                    assert!(!cs.is_satisfied());
+                }
+            }
+        }
+    }
+
+    fn check_permutation(a: &[u64], b: &[u64]) -> TestConstraintSystem<BellmanFr> {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a = a
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                WrappedLc::alloc_num(alloc_num(
+                    &mut cs.namespace(|| format!("a {}", i)),
+                    BellmanFr::from(*v),
+                ))
+            })
+            .collect::<Vec<_>>();
+        let b = b
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                WrappedLc::alloc_num(alloc_num(
+                    &mut cs.namespace(|| format!("b {}", i)),
+                    BellmanFr::from(*v),
+                ))
+            })
+            .collect::<Vec<_>>();
+        assert_permutation(&mut cs.namespace(|| "perm"), &a, &b).unwrap();
+        cs
+    }
+
+    #[test]
+    fn test_assert_permutation() {
+        use rand::rngs::StdRng;
+        use rand::seq::SliceRandom;
+        use rand::{Rng, SeedableRng};
+
+        // Every permutation of 4 elements
+        let mut perms = vec![vec![]];
+        for n in 0..4 {
+            perms = perms
+                .into_iter()
+                .flat_map(|p: Vec<u64>| {
+                    (0..=n).map(move |pos| {
+                        let mut p = p.clone();
+                        p.insert(pos, n as u64);
+                        p
+                    })
+                })
+                .collect();
+        }
+        assert_eq!(perms.len(), 24);
+        for perm in perms {
+            assert!(check_permutation(&[0, 1, 2, 3], &perm).is_satisfied());
+        }
+
+        let mut rng = StdRng::seed_from_u64(123);
+        for n in [0, 1, 2, 3, 5, 8, 13, 16, 33] {
+            for _ in 0..10 {
+                // Values in a small range, so that there are duplicates
+                let a = (0..n).map(|_| rng.gen_range(0..5)).collect::<Vec<u64>>();
+                let mut b = a.clone();
+                b.shuffle(&mut rng);
+                assert!(check_permutation(&a, &b).is_satisfied());
+
+                if n > 0 {
+                    b[0] += 5;
+                    assert!(!check_permutation(&a, &b).is_satisfied());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_assert_permutation_rejects_bad_switches() {
+        let mut cs = check_permutation(&[10, 20, 30, 40], &[30, 10, 40, 20]);
+        assert!(cs.is_satisfied());
+        let path = "perm/network/in 0/swap/boolean";
+        let flipped = if cs.get(path) == BellmanFr::zero() {
+            BellmanFr::one()
+        } else {
+            BellmanFr::zero()
+        };
+        cs.set(path, flipped);
+        assert!(!cs.is_satisfied());
+    }
+
+    fn alloc_nums<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[u64],
+    ) -> Vec<AllocatedNum<BellmanFr>> {
+        vals.iter()
+            .enumerate()
+            .map(|(i, v)| {
+                alloc_num(
+                    &mut cs.namespace(|| format!("val {}", i)),
+                    BellmanFr::from(*v),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_assert_sorted() {
+        for (vals, sorted, strictly_sorted) in [
+            (vec![], true, true),
+            (vec![5], true, true),
+            (vec![1, 2, 3, 65535], true, true),
+            (vec![1, 2, 2, 3], true, false),
+            (vec![1, 3, 2], false, false),
+            (vec![3, 3, 3], true, false),
+            (vec![0, 65536], false, false),
+        ] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let nums = alloc_nums(&mut cs.namespace(|| "vals"), &vals);
+            assert_sorted(&mut cs.namespace(|| "sorted"), &nums, 16).unwrap();
+            assert_eq!(cs.is_satisfied(), sorted);
+
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let nums = alloc_nums(&mut cs.namespace(|| "vals"), &vals);
+            assert_strictly_sorted(&mut cs.namespace(|| "sorted"), &nums, 16).unwrap();
+            assert_eq!(cs.is_satisfied(), strictly_sorted);
+        }
+
+        // Values which are "sorted" only after wrapping around the field modulus
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a = alloc_num(&mut cs.namespace(|| "a"), -BellmanFr::one());
+        let b = alloc_num(&mut cs.namespace(|| "b"), BellmanFr::from(3));
+        assert_strictly_sorted(&mut cs.namespace(|| "sorted"), &[a, b], 16).unwrap();
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_assert_unique() {
+        for (vals, unique) in [
+            (vec![], true),
+            (vec![7], true),
+            (vec![9, 3, 65535, 0, 12], true),
+            (vec![9, 3, 65535, 3, 12], false),
+            (vec![1, 1], false),
+            (vec![65536, 1], false),
+        ] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let nums = alloc_nums(&mut cs.namespace(|| "vals"), &vals);
+            assert_unique(&mut cs.namespace(|| "unique"), &nums, 16).unwrap();
+            assert_eq!(cs.is_satisfied(), unique);
+        }
+    }
+
+    #[test]
+    fn test_inverse_and_div() {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a = alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(7));
+        let b = alloc_num(&mut cs.namespace(|| "b"), BellmanFr::from(3));
+        let zero = alloc_num(&mut cs.namespace(|| "zero"), BellmanFr::zero());
+
+        let inv = inverse(&mut cs.namespace(|| "inv"), a.clone()).unwrap();
+        assert_eq!(
+            inv.get_value().map(|inv| inv * BellmanFr::from(7)),
+            Some(BellmanFr::one())
+        );
+        let quot = div(&mut cs.namespace(|| "div"), a.clone(), b.clone()).unwrap();
+        assert_eq!(
+            quot.get_value().map(|q| q * BellmanFr::from(3)),
+            Some(BellmanFr::from(7))
+        );
+        let quot_zero = div(&mut cs.namespace(|| "div zero"), zero.clone(), b).unwrap();
+        assert_eq!(quot_zero.get_value(), Some(BellmanFr::zero()));
+        assert_nonzero(&mut cs.namespace(|| "nonzero"), a.clone()).unwrap();
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.num_constraints(), 1 + 2 + 2 + 1);
+
+        cs.set("div/a * b_inv/product num", BellmanFr::from(7));
+        assert!(!cs.is_satisfied());
+
+        assert!(matches!(
+            inverse(&mut cs.namespace(|| "inv zero"), zero.clone()),
+            Err(SynthesisError::DivisionByZero)
+        ));
+        assert!(matches!(
+            div(&mut cs.namespace(|| "div by zero"), a, zero.clone()),
+            Err(SynthesisError::DivisionByZero)
+        ));
+        assert!(matches!(
+            assert_nonzero(&mut cs.namespace(|| "nonzero zero"), zero),
+            Err(SynthesisError::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn test_assert_nonzero_rejects_zero() {
+        // Even a prover skipping the witness check can't satisfy it for zero
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a = alloc_num(&mut cs.namespace(|| "a"), BellmanFr::one());
+        assert_nonzero(&mut cs.namespace(|| "nonzero"), a).unwrap();
+        assert!(cs.is_satisfied());
+        cs.set("a/num", BellmanFr::zero());
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_is_zero() {
+        for (val, expected) in [(0, true), (1, false), (12345, false)] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let a = alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(val));
+            let out = is_zero(&mut cs.namespace(|| "is zero"), a).unwrap();
+            assert_eq!(out.get_value(), Some(expected));
+            assert!(cs.is_satisfied());
+            assert_eq!(cs.num_constraints(), 3);
+
+            // Flipping the output can't be compensated by the inverse witness
+            cs.set("is zero/boolean", BellmanFr::from(!expected as u64));
+            for inv in [BellmanFr::zero(), BellmanFr::one(), BellmanFr::from(val)] {
+                cs.set("is zero/num", inv);
+                assert!(!cs.is_satisfied());
+            }
+        }
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a = alloc_num(&mut cs.namespace(|| "a"), -BellmanFr::one());
+        let out = is_zero(&mut cs.namespace(|| "is zero"), a).unwrap();
+        assert_eq!(out.get_value(), Some(false));
+        assert!(cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_is_equal() {
+        for (a_val, b_val) in [(0, 0), (5, 5), (5, 0), (0, 5), (7, 9), (123456, 123456)] {
+            let expected = a_val == b_val;
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let a = alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(a_val));
+            let b = alloc_num(&mut cs.namespace(|| "b"), BellmanFr::from(b_val));
+            let out = is_equal(&mut cs.namespace(|| "is equal"), a, b).unwrap();
+            assert_eq!(out.get_value(), Some(expected));
+            assert!(cs.is_satisfied());
+            assert_eq!(cs.num_constraints(), 3);
+
+            cs.set("is equal/boolean", BellmanFr::from(!expected as u64));
+            for inv in [BellmanFr::zero(), BellmanFr::one(), BellmanFr::from(a_val)] {
+                cs.set("is equal/num", inv);
+                assert!(!cs.is_satisfied());
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_bits_and_sum_bits() {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a = alloc_bits(&mut cs.namespace(|| "a"), 0xa5c3, 16);
+        let b = alloc_bits(&mut cs.namespace(|| "b"), 0xffff, 16);
+        let num = from_bits(&mut cs.namespace(|| "from bits"), a.clone()).unwrap();
+        assert_eq!(num.get_value(), Some(BellmanFr::from(0xa5c3)));
+        let sum = sum_bits(&mut cs.namespace(|| "sum bits"), a, b).unwrap();
+        assert_eq!(sum.get_value(), Some(BellmanFr::from(0xa5c3 + 0xffff)));
+        assert!(cs.is_satisfied());
+
+        cs.set("from bits/num", BellmanFr::from(0xa5c2));
+        assert!(!cs.is_satisfied());
+        cs.set("from bits/num", BellmanFr::from(0xa5c3));
+        cs.set("sum bits/num", BellmanFr::from(0xa5c3 + 0xfffe));
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_to_bits_neg() {
+        for val in [0, 1, 2, 0x1234, 0xffff, 0x10000] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let a = alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(val));
+            let bits = to_bits_neg(&mut cs.namespace(|| "neg"), a, 16).unwrap();
+            assert_eq!(bits_value(&bits), (0x10000 - val) & 0xffff);
+            assert!(cs.is_satisfied());
+
+            // Claiming zero-ness wrongly, or tampering with a bit, is rejected
+            cs.set("neg/is zero/boolean", BellmanFr::from((val != 0) as u64));
+            assert!(!cs.is_satisfied());
+            cs.set("neg/is zero/boolean", BellmanFr::from((val == 0) as u64));
+            let bit0 = bits[0].get_value().unwrap();
+            cs.set("neg/bit 0/boolean", BellmanFr::from(!bit0 as u64));
+            assert!(!cs.is_satisfied());
+        }
+        // 2^n - a doesn't fit in n bits when a is too large
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a = alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(0x10001));
+        to_bits_neg(&mut cs.namespace(|| "neg"), a, 16).unwrap();
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_assert_equal() {
+        for (enabled, a_val, b_val, satisfied) in [
+            (true, 5, 5, true),
+            (true, 5, 6, false),
+            (false, 5, 5, true),
+            (false, 5, 6, true),
+        ] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let enabled =
+                AllocatedBit::alloc(&mut cs.namespace(|| "enabled"), Some(enabled)).unwrap();
+            let a = alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(a_val));
+            let b = alloc_num(&mut cs.namespace(|| "b"), BellmanFr::from(b_val));
+            assert_equal(&mut cs.namespace(|| "assert equal"), enabled, a, b).unwrap();
+            assert_eq!(cs.is_satisfied(), satisfied);
+
+            // The intermediate witness can't be used to bypass the check
+            if !satisfied {
+                for v in [0, a_val, b_val] {
+                    cs.set("assert equal/", BellmanFr::from(v));
+                    assert!(!cs.is_satisfied());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_lte_rejects_forged_output() {
+        for (a_val, b_val) in [(3, 5), (5, 5), (6, 5)] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let a = alloc_num(&mut cs.namespace(|| "a"), BellmanFr::from(a_val));
+            let b = alloc_num(&mut cs.namespace(|| "b"), BellmanFr::from(b_val));
+            let out = lte_bits(&mut cs.namespace(|| "lte"), a, b, 8).unwrap();
+            assert_eq!(out.get_value(), Some(a_val <= b_val));
+            assert!(cs.is_satisfied());
+
+            let path = "lte/diff/bit 8/boolean";
+            let forged = BellmanFr::one() - cs.get(path);
+            cs.set(path, forged);
+            assert!(!cs.is_satisfied());
+        }
+    }
+
+    #[test]
+    fn test_lte_64_bits() {
+        for (a_val, b_val, sat) in [
+            (3, 5, true),
+            (u64::MAX as u128, u64::MAX as u128, true),
+            (u64::MAX as u128 + 1, 0, false),
+        ] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let a = alloc_num(&mut cs.namespace(|| "a"), fr_from_u128(a_val));
+            let b = alloc_num(&mut cs.namespace(|| "b"), fr_from_u128(b_val));
+            let out = lte(&mut cs.namespace(|| "lte"), a, b).unwrap();
+            if sat {
+                assert_eq!(out.get_value(), Some(a_val <= b_val));
+            }
+            assert_eq!(cs.is_satisfied(), sat);
+        }
+    }
 }
@@ -0,0 +1,389 @@
+// Differential tests, running native functions and their gadgets on random inputs
+// Adding a gadget means adding a Case to cases()
+
+use crate::eddsa::groth16::{
+    add_const_point, add_point, mul_cofactor, mul_const_point, mul_point, verify_eddsa,
+    AllocatedPoint,
+};
+use crate::merkle::groth16::AllocatedProof;
+use crate::poseidon::groth16::{hash, poseidon, poseidon4};
+use crate::poseidon::{PoseidonSponge, POSEIDON_ARITY2, POSEIDON_ARITY3, POSEIDON_ARITY4};
+use crate::{commitment, encryption, poseidon2, BellmanFr};
+
+use bazuka::crypto::jubjub::{PointAffine, BASE_COFACTOR};
+use bazuka::zk::{PoseidonHasher, ZkHasher, ZkScalar};
+use bellman::gadgets::boolean::AllocatedBit;
+use bellman::gadgets::num::AllocatedNum;
+use bellman::gadgets::test::TestConstraintSystem;
+use bellman::{ConstraintSystem, SynthesisError};
+use ff::{Field, PrimeField};
+use num_bigint::BigUint;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const ITERATIONS: usize = 8;
+
+type Cs = TestConstraintSystem<BellmanFr>;
+type Nums = Vec<AllocatedNum<BellmanFr>>;
+
+struct Case {
+    name: &'static str,
+    inputs: fn(&mut StdRng) -> Vec<ZkScalar>,
+    // None when the gadget is expected to be unsatisfiable on the inputs
+    native: fn(&[ZkScalar]) -> Option<Vec<ZkScalar>>,
+    gadget: fn(&mut Cs, &[AllocatedNum<BellmanFr>]) -> Result<Nums, SynthesisError>,
+}
+
+fn scalars(rng: &mut StdRng, count: usize) -> Vec<ZkScalar> {
+    (0..count).map(|_| ZkScalar::random(&mut *rng)).collect()
+}
+
+fn random_point(rng: &mut StdRng) -> PointAffine {
+    BASE_COFACTOR.multiply(&ZkScalar::random(rng))
+}
+
+fn point(vals: &[ZkScalar]) -> PointAffine {
+    PointAffine(vals[0], vals[1])
+}
+
+fn alloc_point(vals: &[AllocatedNum<BellmanFr>]) -> AllocatedPoint {
+    AllocatedPoint {
+        x: vals[0].clone(),
+        y: vals[1].clone(),
+    }
+}
+
+fn sum(mut a: PointAffine, b: PointAffine) -> PointAffine {
+    a.add_assign(&b);
+    a
+}
+
+fn points(points: &[PointAffine]) -> Vec<ZkScalar> {
+    points.iter().flat_map(|p| [p.0, p.1]).collect()
+}
+
+fn nums(point: AllocatedPoint) -> Nums {
+    vec![point.x, point.y]
+}
+
+// Jubjub prime subgroup order
+fn subgroup_order() -> BigUint {
+    BigUint::parse_bytes(
+        b"6554484396890773809930967563523245729705921265872317281365359162392183254199",
+        10,
+    )
+    .unwrap()
+}
+
+fn to_biguint(v: &ZkScalar) -> BigUint {
+    BigUint::from_bytes_le(v.to_repr().as_ref())
+}
+
+fn from_biguint(v: &BigUint) -> ZkScalar {
+    let mut repr = <ZkScalar as PrimeField>::Repr::default();
+    let bytes = v.to_bytes_le();
+    repr.as_mut()[..bytes.len()].copy_from_slice(&bytes);
+    ZkScalar::from_repr(repr).unwrap()
+}
+
+fn eddsa_hash(pk: &PointAffine, msg: ZkScalar, sig_r: &PointAffine) -> ZkScalar {
+    PoseidonHasher::hash(&[sig_r.0, sig_r.1, pk.0, pk.1, msg])
+}
+
+// [pk.x, pk.y, msg, r.x, r.y, s], signed with a random key
+fn eddsa_signature(rng: &mut StdRng) -> Vec<ZkScalar> {
+    let order = subgroup_order();
+    let sk = ZkScalar::random(&mut *rng);
+    let nonce = ZkScalar::random(&mut *rng);
+    let msg = ZkScalar::random(&mut *rng);
+    let pk = BASE_COFACTOR.multiply(&sk);
+    let sig_r = BASE_COFACTOR.multiply(&nonce);
+    let h = eddsa_hash(&pk, msg, &sig_r);
+    // 8 * (R + hA) == sB
+    let sig_s =
+        (BigUint::from(8u32) * (to_biguint(&nonce) + to_biguint(&h) * to_biguint(&sk))) % order;
+    vec![pk.0, pk.1, msg, sig_r.0, sig_r.1, from_biguint(&sig_s)]
+}
+
+fn eddsa_verify(vals: &[ZkScalar]) -> Option<Vec<ZkScalar>> {
+    let (pk, msg, sig_r, sig_s) = (point(&vals[0..2]), vals[2], point(&vals[3..5]), vals[5]);
+    let mut r_plus_ha = pk.multiply(&eddsa_hash(&pk, msg, &sig_r));
+    r_plus_ha.add_assign(&sig_r);
+    r_plus_ha = r_plus_ha.multiply(&ZkScalar::from(8));
+    (r_plus_ha == BASE_COFACTOR.multiply(&sig_s)).then(Vec::new)
+}
+
+fn eddsa_gadget(cs: &mut Cs, vals: &[AllocatedNum<BellmanFr>]) -> Result<Nums, SynthesisError> {
+    let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(true))?;
+    verify_eddsa(
+        &mut cs.namespace(|| "verify"),
+        enabled,
+        alloc_point(&vals[0..2]),
+        vals[2].clone(),
+        alloc_point(&vals[3..5]),
+        vals[5].clone(),
+    )?;
+    Ok(vec![])
+}
+
+fn cases() -> Vec<Case> {
+    vec![
+        Case {
+            name: "poseidon4",
+            inputs: |rng| scalars(rng, 4),
+            native: |v| {
+                Some(vec![bazuka::zk::poseidon4::poseidon4(
+                    v[0], v[1], v[2], v[3],
+                )])
+            },
+            gadget: |cs, v| {
+                Ok(vec![poseidon4(
+                    cs,
+                    v[0].clone(),
+                    v[1].clone(),
+                    v[2].clone(),
+                    v[3].clone(),
+                )?])
+            },
+        },
+        Case {
+            name: "poseidon",
+            inputs: |rng| {
+                let len = rng.gen_range(1..12);
+                scalars(rng, len)
+            },
+            native: |v| Some(vec![PoseidonHasher::hash(v)]),
+            gadget: |cs, v| Ok(vec![poseidon(cs, v)?]),
+        },
+        Case {
+            name: "poseidon arity 2",
+            inputs: |rng| scalars(rng, 2),
+            native: |v| Some(vec![POSEIDON_ARITY2.hash(v)]),
+            gadget: |cs, v| Ok(vec![hash(cs, &POSEIDON_ARITY2, v)?]),
+        },
+        Case {
+            name: "poseidon arity 3",
+            inputs: |rng| scalars(rng, 3),
+            native: |v| Some(vec![POSEIDON_ARITY3.hash(v)]),
+            gadget: |cs, v| Ok(vec![hash(cs, &POSEIDON_ARITY3, v)?]),
+        },
+        Case {
+            name: "poseidon sponge",
+            inputs: |rng| {
+                let len = rng.gen_range(0..12);
+                scalars(rng, len)
+            },
+            native: |v| {
+                let mut sponge = PoseidonSponge::new(&POSEIDON_ARITY4, ZkScalar::from(1));
+                sponge.absorb(v);
+                Some(vec![sponge.squeeze(), sponge.squeeze()])
+            },
+            gadget: |cs, v| {
+                let mut sponge = crate::poseidon::groth16::PoseidonSponge::new(
+                    &POSEIDON_ARITY4,
+                    ZkScalar::from(1),
+                );
+                sponge.absorb(&mut *cs, v)?;
+                Ok(vec![sponge.squeeze(&mut *cs)?, sponge.squeeze(&mut *cs)?])
+            },
+        },
+        Case {
+            name: "poseidon2",
+            inputs: |rng| {
+                let len = rng.gen_range(1..12);
+                scalars(rng, len)
+            },
+            native: |v| Some(vec![poseidon2::hash(v)]),
+            gadget: |cs, v| Ok(vec![poseidon2::groth16::hash(cs, v)?]),
+        },
+        Case {
+            name: "owner_key",
+            inputs: |rng| scalars(rng, 1),
+            native: |v| Some(vec![commitment::owner_key(v[0])]),
+            gadget: |cs, v| Ok(vec![commitment::groth16::owner_key(cs, v[0].clone())?]),
+        },
+        Case {
+            name: "commit",
+            inputs: |rng| scalars(rng, 3),
+            native: |v| Some(vec![commitment::commit(v[0], v[1], v[2])]),
+            gadget: |cs, v| {
+                Ok(vec![commitment::groth16::commit(
+                    cs,
+                    v[0].clone(),
+                    v[1].clone(),
+                    v[2].clone(),
+                )?])
+            },
+        },
+        Case {
+            name: "nullifier",
+            // Value, secret, blinding, position, the 3 siblings of a single level tree
+            // and its root
+            inputs: |rng| {
+                let mut v = scalars(rng, 3);
+                let position = rng.gen_range(0..4);
+                let siblings = scalars(rng, 3);
+                let owner = commitment::owner_key(v[1]);
+                let mut leaves = siblings.clone();
+                leaves.insert(position, commitment::commit(v[0], owner, v[2]));
+                v.push(ZkScalar::from(position as u64));
+                v.extend(siblings);
+                v.push(PoseidonHasher::hash(&leaves));
+                v
+            },
+            native: |v| Some(vec![commitment::nullifier(v[1], v[3])]),
+            gadget: |cs, v| {
+                let proof = AllocatedProof::<1>::from_levels(vec![[
+                    v[4].clone(),
+                    v[5].clone(),
+                    v[6].clone(),
+                ]])?;
+                let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(true))?;
+                Ok(vec![
+                    commitment::groth16::nullifier::<PoseidonHasher, _, 1>(
+                        cs,
+                        enabled,
+                        v[0].clone(),
+                        v[1].clone(),
+                        v[2].clone(),
+                        v[3].clone(),
+                        proof,
+                        v[7].clone(),
+                    )?,
+                ])
+            },
+        },
+        Case {
+            name: "add_point",
+            inputs: |rng| points(&[random_point(rng), random_point(rng)]),
+            native: |v| Some(points(&[sum(point(&v[0..2]), point(&v[2..4]))])),
+            gadget: |cs, v| {
+                Ok(nums(add_point(
+                    cs,
+                    alloc_point(&v[0..2]),
+                    alloc_point(&v[2..4]),
+                )?))
+            },
+        },
+        Case {
+            name: "add_const_point",
+            inputs: |rng| points(&[random_point(rng)]),
+            native: |v| Some(points(&[sum(point(v), *BASE_COFACTOR)])),
+            gadget: |cs, v| Ok(nums(add_const_point(cs, alloc_point(v), *BASE_COFACTOR)?)),
+        },
+        Case {
+            name: "mul_point",
+            inputs: |rng| {
+                let mut vals = points(&[random_point(rng)]);
+                vals.push(ZkScalar::random(rng));
+                vals
+            },
+            native: |v| Some(points(&[point(v).multiply(&v[2])])),
+            gadget: |cs, v| Ok(nums(mul_point(cs, alloc_point(v), v[2].clone())?)),
+        },
+        Case {
+            name: "mul_const_point",
+            inputs: |rng| scalars(rng, 1),
+            native: |v| Some(points(&[BASE_COFACTOR.multiply(&v[0])])),
+            gadget: |cs, v| Ok(nums(mul_const_point(cs, *BASE_COFACTOR, v[0].clone())?)),
+        },
+        Case {
+            name: "mul_cofactor",
+            inputs: |rng| points(&[random_point(rng)]),
+            native: |v| Some(points(&[point(v).multiply(&ZkScalar::from(8))])),
+            gadget: |cs, v| Ok(nums(mul_cofactor(cs, alloc_point(v))?)),
+        },
+        Case {
+            name: "verify_eddsa",
+            inputs: eddsa_signature,
+            native: eddsa_verify,
+            gadget: eddsa_gadget,
+        },
+        Case {
+            name: "verify_eddsa forged",
+            inputs: |rng| {
+                let mut vals = eddsa_signature(rng);
+                vals[5] += ZkScalar::one();
+                vals
+            },
+            native: eddsa_verify,
+            gadget: eddsa_gadget,
+        },
+        Case {
+            name: "verify_eddsa wrong message",
+            inputs: |rng| {
+                let mut vals = eddsa_signature(rng);
+                vals[2] += ZkScalar::one();
+                vals
+            },
+            native: eddsa_verify,
+            gadget: eddsa_gadget,
+        },
+        Case {
+            name: "encrypt",
+            inputs: |rng| {
+                let mut vals = points(&[random_point(rng)]);
+                let len = rng.gen_range(1..10);
+                vals.extend(scalars(rng, len));
+                vals
+            },
+            native: |v| {
+                let (mut out, tag) = encryption::encrypt(point(v), v[2], &v[3..]);
+                out.push(tag);
+                Some(out)
+            },
+            gadget: |cs, v| {
+                let (mut out, tag) =
+                    encryption::groth16::encrypt(cs, alloc_point(v), v[2].clone(), &v[3..])?;
+                out.push(tag);
+                Ok(out)
+            },
+        },
+    ]
+}
+
+#[test]
+fn test_native_and_gadget_agree() {
+    let mut rng = StdRng::seed_from_u64(0x7a65656b6974);
+    for case in cases() {
+        for iteration in 0..ITERATIONS {
+            let inputs = (case.inputs)(&mut rng);
+            let expected = (case.native)(&inputs);
+
+            let mut cs = Cs::new();
+            let nums = inputs
+                .iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    AllocatedNum::alloc(cs.namespace(|| format!("input {}", i)), || Ok((*v).into()))
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            let outputs = (case.gadget)(&mut cs, &nums).unwrap();
+
+            match expected {
+                Some(expected) => {
+                    let outputs = outputs
+                        .iter()
+                        .map(|o| o.get_value().unwrap())
+                        .collect::<Vec<_>>();
+                    let expected = expected
+                        .into_iter()
+                        .map(Into::into)
+                        .collect::<Vec<BellmanFr>>();
+                    assert_eq!(outputs, expected, "{} #{}", case.name, iteration);
+                    assert!(
+                        cs.is_satisfied(),
+                        "{} #{}: {:?}",
+                        case.name,
+                        iteration,
+                        cs.which_is_unsatisfied()
+                    );
+                }
+                None => {
+                    assert!(!cs.is_satisfied(), "{} #{}", case.name, iteration);
+                }
+            }
+        }
+    }
+}
@@ -29,12 +29,12 @@ pub fn add_point<CS: ConstraintSystem<BellmanFr>>(
                 sum.add_assign(&PointAffine(b_x.into(), b_y.into()));
                 sum
             });
-    let sum_x = AllocatedNum::alloc(&mut *cs, || {
+    let sum_x = AllocatedNum::alloc(cs.namespace(|| "sum x"), || {
         sum_value
             .map(|v| v.0.into())
             .ok_or(SynthesisError::AssignmentMissing)
     })?;
-    let sum_y = AllocatedNum::alloc(&mut *cs, || {
+    let sum_y = AllocatedNum::alloc(cs.namespace(|| "sum y"), || {
         sum_value
             .map(|v| v.1.into())
             .ok_or(SynthesisError::AssignmentMissing)
@@ -42,12 +42,12 @@ pub fn add_point<CS: ConstraintSystem<BellmanFr>>(
 
     let curve_d: BellmanFr = D.clone().into();
     let common =
-        a.x.mul(&mut *cs, &b.x)?
-            .mul(&mut *cs, &a.y)?
-            .mul(&mut *cs, &b.y)?; // * CURVE_D
+        a.x.mul(cs.namespace(|| "ax * bx"), &b.x)?
+            .mul(cs.namespace(|| "ax * bx * ay"), &a.y)?
+            .mul(cs.namespace(|| "ax * bx * ay * by"), &b.y)?; // * CURVE_D
 
-    let x_1 = a.x.mul(&mut *cs, &b.y)?;
-    let x_2 = a.y.mul(&mut *cs, &b.x)?;
+    let x_1 = a.x.mul(cs.namespace(|| "x_1"), &b.y)?;
+    let x_2 = a.y.mul(cs.namespace(|| "x_2"), &b.x)?;
     cs.enforce(
         || "x_1 + x_2 == sum_x * (1 + common)",
         |lc| lc + CS::one() + (curve_d, common.get_variable()),
@@ -55,8 +55,8 @@ pub fn add_point<CS: ConstraintSystem<BellmanFr>>(
         |lc| lc + x_1.get_variable() + x_2.get_variable(),
     );
 
-    let y_1 = a.y.mul(&mut *cs, &b.y)?;
-    let y_2 = a.x.mul(&mut *cs, &b.x)?; // * CURVE_A
+    let y_1 = a.y.mul(cs.namespace(|| "y_1"), &b.y)?;
+    let y_2 = a.x.mul(cs.namespace(|| "y_2"), &b.x)?; // * CURVE_A
     cs.enforce(
         || "y_1 - y_2 == sum_y * (1 - common)",
         |lc| lc + CS::one() - (curve_d, common.get_variable()),
@@ -77,12 +77,12 @@ pub fn add_const_point<CS: ConstraintSystem<BellmanFr>>(
         sum.add_assign(&b);
         sum
     });
-    let sum_x = AllocatedNum::alloc(&mut *cs, || {
+    let sum_x = AllocatedNum::alloc(cs.namespace(|| "sum x"), || {
         sum_value
             .map(|v| v.0.into())
             .ok_or(SynthesisError::AssignmentMissing)
     })?;
-    let sum_y = AllocatedNum::alloc(&mut *cs, || {
+    let sum_y = AllocatedNum::alloc(cs.namespace(|| "sum y"), || {
         sum_value
             .map(|v| v.1.into())
             .ok_or(SynthesisError::AssignmentMissing)
@@ -91,7 +91,7 @@ pub fn add_const_point<CS: ConstraintSystem<BellmanFr>>(
     let bx: BellmanFr = b.0.into();
     let by: BellmanFr = b.1.into();
     let curve_d_bx_by: BellmanFr = Into::<BellmanFr>::into(D.clone()) * bx * by;
-    let common = a.x.mul(&mut *cs, &a.y)?; // * CURVE_D * bx * by
+    let common = a.x.mul(cs.namespace(|| "ax * ay"), &a.y)?; // * CURVE_D * bx * by
 
     cs.enforce(
         || "x_1 + x_2 == sum_x * (1 + common)",
@@ -118,33 +118,41 @@ pub fn mul_point<CS: ConstraintSystem<BellmanFr>>(
     base: AllocatedPoint,
     b: AllocatedNum<BellmanFr>,
 ) -> Result<AllocatedPoint, SynthesisError> {
-    let bits: Vec<Boolean> = b.to_bits_le_strict(&mut *cs)?.into_iter().rev().collect();
+    let bits: Vec<Boolean> = b
+        .to_bits_le_strict(cs.namespace(|| "bits"))?
+        .into_iter()
+        .rev()
+        .collect();
+    let init_x = common::groth16::mux(
+        &mut cs.namespace(|| "init x"),
+        &bits[0],
+        &WrappedLc::zero(),
+        &WrappedLc::alloc_num(base.x.clone()),
+    )?;
+    let init_y = common::groth16::mux(
+        &mut cs.namespace(|| "init y"),
+        &bits[0],
+        &WrappedLc::constant::<CS>(BellmanFr::one()),
+        &WrappedLc::alloc_num(base.y.clone()),
+    )?;
     let mut result = AllocatedPoint {
-        x: common::groth16::mux(
-            &mut *cs,
-            &bits[0],
-            &WrappedLc::zero(),
-            &WrappedLc::alloc_num(base.x.clone()),
-        )?,
-        y: common::groth16::mux(
-            &mut *cs,
-            &bits[0],
-            &WrappedLc::constant::<CS>(BellmanFr::one()),
-            &WrappedLc::alloc_num(base.y.clone()),
-        )?,
+        x: init_x,
+        y: init_y,
     };
-    for bit in bits[1..].iter() {
-        result = add_point(&mut *cs, result.clone(), result)?;
-        let result_plus_base = add_point(&mut *cs, result.clone(), base.clone())?;
+    for (i, bit) in bits[1..].iter().enumerate() {
+        let cs = &mut cs.namespace(|| format!("bit {}", i + 1));
+        result = add_point(&mut cs.namespace(|| "double"), result.clone(), result)?;
+        let result_plus_base =
+            add_point(&mut cs.namespace(|| "add"), result.clone(), base.clone())?;
         let result_x = common::groth16::mux(
-            &mut *cs,
-            &bit,
+            &mut cs.namespace(|| "x"),
+            bit,
             &WrappedLc::alloc_num(result.x),
             &WrappedLc::alloc_num(result_plus_base.x),
         )?;
         let result_y = common::groth16::mux(
-            &mut *cs,
-            &bit,
+            &mut cs.namespace(|| "y"),
+            bit,
             &WrappedLc::alloc_num(result.y),
             &WrappedLc::alloc_num(result_plus_base.y),
         )?;
@@ -161,33 +169,41 @@ pub fn mul_const_point<CS: ConstraintSystem<BellmanFr>>(
     base: PointAffine,
     b: AllocatedNum<BellmanFr>,
 ) -> Result<AllocatedPoint, SynthesisError> {
-    let bits: Vec<Boolean> = b.to_bits_le_strict(&mut *cs)?.into_iter().rev().collect();
+    let bits: Vec<Boolean> = b
+        .to_bits_le_strict(cs.namespace(|| "bits"))?
+        .into_iter()
+        .rev()
+        .collect();
+    let init_x = common::groth16::mux(
+        &mut cs.namespace(|| "init x"),
+        &bits[0],
+        &WrappedLc::zero(),
+        &WrappedLc::constant::<CS>(base.0.into()),
+    )?;
+    let init_y = common::groth16::mux(
+        &mut cs.namespace(|| "init y"),
+        &bits[0],
+        &WrappedLc::constant::<CS>(BellmanFr::one()),
+        &WrappedLc::constant::<CS>(base.1.into()),
+    )?;
     let mut result = AllocatedPoint {
-        x: common::groth16::mux(
-            &mut *cs,
-            &bits[0],
-            &WrappedLc::zero(),
-            &WrappedLc::constant::<CS>(base.0.into()),
-        )?,
-        y: common::groth16::mux(
-            &mut *cs,
-            &bits[0],
-            &WrappedLc::constant::<CS>(BellmanFr::one()),
-            &WrappedLc::constant::<CS>(base.1.into()),
-        )?,
+        x: init_x,
+        y: init_y,
     };
-    for bit in bits[1..].iter() {
-        result = add_point(&mut *cs, result.clone(), result)?;
-        let result_plus_base = add_const_point(&mut *cs, result.clone(), base.clone())?;
+    for (i, bit) in bits[1..].iter().enumerate() {
+        let cs = &mut cs.namespace(|| format!("bit {}", i + 1));
+        result = add_point(&mut cs.namespace(|| "double"), result.clone(), result)?;
+        let result_plus_base =
+            add_const_point(&mut cs.namespace(|| "add"), result.clone(), base.clone())?;
         let result_x = common::groth16::mux(
-            &mut *cs,
-            &bit,
+            &mut cs.namespace(|| "x"),
+            bit,
             &WrappedLc::alloc_num(result.x),
             &WrappedLc::alloc_num(result_plus_base.x),
         )?;
         let result_y = common::groth16::mux(
-            &mut *cs,
-            &bit,
+            &mut cs.namespace(|| "y"),
+            bit,
             &WrappedLc::alloc_num(result.y),
             &WrappedLc::alloc_num(result_plus_base.y),
         )?;
@@ -204,9 +220,9 @@ pub fn mul_cofactor<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
     mut point: AllocatedPoint,
 ) -> Result<AllocatedPoint, SynthesisError> {
-    point = add_point(&mut *cs, point.clone(), point)?;
-    point = add_point(&mut *cs, point.clone(), point)?;
-    point = add_point(&mut *cs, point.clone(), point)?;
+    point = add_point(&mut cs.namespace(|| "double 0"), point.clone(), point)?;
+    point = add_point(&mut cs.namespace(|| "double 1"), point.clone(), point)?;
+    point = add_point(&mut cs.namespace(|| "double 2"), point.clone(), point)?;
     Ok(point)
 }
 
@@ -220,7 +236,7 @@ pub fn verify_eddsa<CS: ConstraintSystem<BellmanFr>>(
 ) -> Result<(), SynthesisError> {
     // h=H(R,A,M)
     let h = poseidon::groth16::poseidon(
-        &mut *cs,
+        &mut cs.namespace(|| "h"),
         &[
             sig_r.x.clone(),
             sig_r.y.clone(),
@@ -230,13 +246,18 @@ pub fn verify_eddsa<CS: ConstraintSystem<BellmanFr>>(
         ],
     )?;
 
-    let sb = mul_const_point(&mut *cs, BASE_COFACTOR.clone(), sig_s)?;
+    let sb = mul_const_point(&mut cs.namespace(|| "sb"), BASE_COFACTOR.clone(), sig_s)?;
 
-    let mut r_plus_ha = mul_point(&mut *cs, pk.clone(), h)?;
-    r_plus_ha = add_point(&mut *cs, r_plus_ha.clone(), sig_r)?;
-    r_plus_ha = mul_cofactor(&mut *cs, r_plus_ha)?;
+    let mut r_plus_ha = mul_point(&mut cs.namespace(|| "ha"), pk.clone(), h)?;
+    r_plus_ha = add_point(&mut cs.namespace(|| "r + ha"), r_plus_ha.clone(), sig_r)?;
+    r_plus_ha = mul_cofactor(&mut cs.namespace(|| "cofactor"), r_plus_ha)?;
 
-    common::groth16::assert_equal(cs, enabled.clone(), r_plus_ha.x, sb.x)?;
-    common::groth16::assert_equal(cs, enabled, r_plus_ha.y, sb.y)?;
+    common::groth16::assert_equal(
+        &mut cs.namespace(|| "x equal"),
+        enabled.clone(),
+        r_plus_ha.x,
+        sb.x,
+    )?;
+    common::groth16::assert_equal(&mut cs.namespace(|| "y equal"), enabled, r_plus_ha.y, sb.y)?;
     Ok(())
 }
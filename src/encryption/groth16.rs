@@ -0,0 +1,271 @@
+use super::ENCRYPTION_DOMAIN;
+use crate::common::groth16::WrappedLc;
+use crate::eddsa::groth16::{mul_point, AllocatedPoint};
+use crate::poseidon::groth16::{compress, PoseidonSponge};
+use crate::poseidon::POSEIDON_ARITY4;
+use crate::BellmanFr;
+
+use bellman::gadgets::num::AllocatedNum;
+use bellman::{ConstraintSystem, SynthesisError};
+
+// Same as native shared_key
+pub fn shared_key<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    sk: AllocatedNum<BellmanFr>,
+    pk: AllocatedPoint,
+) -> Result<AllocatedPoint, SynthesisError> {
+    mul_point(cs, pk, sk)
+}
+
+fn keyed_sponge<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    key: AllocatedPoint,
+    nonce: AllocatedNum<BellmanFr>,
+    len: usize,
+) -> Result<PoseidonSponge<'static>, SynthesisError> {
+    let len = compress(
+        &mut cs.namespace(|| "len"),
+        WrappedLc::constant::<CS>(BellmanFr::from(len as u64)),
+    )?;
+    let mut sponge = PoseidonSponge::new(&POSEIDON_ARITY4, *ENCRYPTION_DOMAIN);
+    sponge.absorb(&mut *cs, &[key.x, key.y, nonce, len])?;
+    Ok(sponge)
+}
+
+// Encrypt msg (Same as native encrypt), returning the ciphertext and the tag
+// A constraint per element, plus a permutation per 4 elements and one for the tag
+pub fn encrypt<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    key: AllocatedPoint,
+    nonce: AllocatedNum<BellmanFr>,
+    msg: &[AllocatedNum<BellmanFr>],
+) -> Result<(Vec<AllocatedNum<BellmanFr>>, AllocatedNum<BellmanFr>), SynthesisError> {
+    let mut sponge = keyed_sponge(&mut *cs, key, nonce, msg.len())?;
+    let mut ciphertext = Vec::new();
+    for (i, chunk) in msg.chunks(POSEIDON_ARITY4.arity()).enumerate() {
+        let cs = &mut cs.namespace(|| format!("block {}", i));
+        let mut block = Vec::new();
+        for (j, m) in chunk.iter().enumerate() {
+            let keystream = sponge.squeeze_lc(&mut *cs)?;
+            block.push(compress(
+                &mut cs.namespace(|| format!("ciphertext {}", j)),
+                WrappedLc::alloc_num(m.clone()) + &keystream,
+            )?);
+        }
+        sponge.absorb(&mut *cs, &block)?;
+        ciphertext.extend(block);
+    }
+    let tag = sponge.squeeze(&mut cs.namespace(|| "tag"))?;
+    Ok((ciphertext, tag))
+}
+
+// Decrypt ciphertext (Same as native decrypt), unsatisfiable if the tag doesn't match
+pub fn decrypt<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    key: AllocatedPoint,
+    nonce: AllocatedNum<BellmanFr>,
+    ciphertext: &[AllocatedNum<BellmanFr>],
+    tag: AllocatedNum<BellmanFr>,
+) -> Result<Vec<AllocatedNum<BellmanFr>>, SynthesisError> {
+    let mut sponge = keyed_sponge(&mut *cs, key, nonce, ciphertext.len())?;
+    let mut msg = Vec::new();
+    for (i, chunk) in ciphertext.chunks(POSEIDON_ARITY4.arity()).enumerate() {
+        let cs = &mut cs.namespace(|| format!("block {}", i));
+        for (j, c) in chunk.iter().enumerate() {
+            let keystream = sponge.squeeze_lc(&mut *cs)?;
+            msg.push(compress(
+                &mut cs.namespace(|| format!("message {}", j)),
+                WrappedLc::alloc_num(c.clone()) - &keystream,
+            )?);
+        }
+        sponge.absorb(&mut *cs, chunk)?;
+    }
+    let expected_tag = sponge.squeeze(&mut cs.namespace(|| "tag"))?;
+    cs.enforce(
+        || "tag == expected_tag",
+        |lc| lc + tag.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + expected_tag.get_variable(),
+    );
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encryption;
+    use bazuka::crypto::jubjub::{PointAffine, BASE_COFACTOR};
+    use bazuka::zk::ZkScalar;
+    use bellman::gadgets::test::TestConstraintSystem;
+
+    fn alloc<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[ZkScalar],
+    ) -> Vec<AllocatedNum<BellmanFr>> {
+        vals.iter()
+            .enumerate()
+            .map(|(i, v)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("val {}", i)), || Ok((*v).into()))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn alloc_point<CS: ConstraintSystem<BellmanFr>>(cs: &mut CS, p: PointAffine) -> AllocatedPoint {
+        let nums = alloc(cs, &[p.0, p.1]);
+        AllocatedPoint {
+            x: nums[0].clone(),
+            y: nums[1].clone(),
+        }
+    }
+
+    #[test]
+    fn test_native_encryption() {
+        let key = BASE_COFACTOR.multiply(&ZkScalar::from(1234));
+        let nonce = ZkScalar::from(5);
+        for len in [0, 1, 4, 7] {
+            let msg = (0..len)
+                .map(|i| ZkScalar::from(i as u64 + 1000))
+                .collect::<Vec<_>>();
+            let (ciphertext, tag) = encryption::encrypt(key, nonce, &msg);
+            assert_eq!(ciphertext.len(), len);
+            assert_eq!(
+                encryption::decrypt(key, nonce, &ciphertext, tag),
+                Some(msg.clone())
+            );
+            assert_eq!(
+                encryption::decrypt(key, nonce + ZkScalar::from(1), &ciphertext, tag),
+                None
+            );
+            if len > 0 {
+                let mut tampered = ciphertext.clone();
+                tampered[len - 1] += ZkScalar::from(1);
+                assert_eq!(encryption::decrypt(key, nonce, &tampered, tag), None);
+                assert_eq!(
+                    encryption::decrypt(key, nonce, &ciphertext[..len - 1], tag),
+                    None
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_encryption_with_ecdh() {
+        let (sender_sk, recipient_sk) = (ZkScalar::from(111), ZkScalar::from(222));
+        let recipient_pk = BASE_COFACTOR.multiply(&recipient_sk);
+        let sender_pk = BASE_COFACTOR.multiply(&sender_sk);
+        let key = encryption::shared_key(sender_sk, recipient_pk);
+        assert_eq!(key, encryption::shared_key(recipient_sk, sender_pk));
+
+        let nonce = ZkScalar::from(9);
+        let msg = (0..6).map(|i| ZkScalar::from(i * 7)).collect::<Vec<_>>();
+        let (expected_ciphertext, expected_tag) = encryption::encrypt(key, nonce, &msg);
+
+        // Sender proves the ciphertext is the encryption of msg for recipient_pk
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let sk = alloc(&mut cs.namespace(|| "sk"), &[sender_sk]).remove(0);
+        let pk = alloc_point(&mut cs.namespace(|| "pk"), recipient_pk);
+        let nonce_num = alloc(&mut cs.namespace(|| "nonce"), &[nonce]).remove(0);
+        let msg_nums = alloc(&mut cs.namespace(|| "msg"), &msg);
+        let key_point = shared_key(&mut cs.namespace(|| "ecdh"), sk, pk).unwrap();
+        let (ciphertext, tag) = encrypt(
+            &mut cs.namespace(|| "encrypt"),
+            key_point.clone(),
+            nonce_num.clone(),
+            &msg_nums,
+        )
+        .unwrap();
+        assert_eq!(
+            ciphertext
+                .iter()
+                .map(|c| c.get_value().unwrap())
+                .collect::<Vec<_>>(),
+            expected_ciphertext
+                .iter()
+                .map(|c| (*c).into())
+                .collect::<Vec<BellmanFr>>()
+        );
+        assert_eq!(tag.get_value(), Some(expected_tag.into()));
+
+        let decrypted = decrypt(
+            &mut cs.namespace(|| "decrypt"),
+            key_point,
+            nonce_num,
+            &ciphertext,
+            tag,
+        )
+        .unwrap();
+        for (d, m) in decrypted.iter().zip(msg.iter()) {
+            assert_eq!(d.get_value(), Some((*m).into()));
+        }
+        assert!(cs.is_satisfied());
+
+        // The expected tag can't be forged by the prover
+        cs.set("decrypt/tag/output 6/num", BellmanFr::from(1));
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_encryption_cost() {
+        let key = BASE_COFACTOR.multiply(&ZkScalar::from(1234));
+        let cost = |len: u64| {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let key = alloc_point(&mut cs.namespace(|| "key"), key);
+            let nonce = alloc(&mut cs.namespace(|| "nonce"), &[ZkScalar::from(5)]).remove(0);
+            let msg = alloc(
+                &mut cs.namespace(|| "msg"),
+                &(0..len).map(ZkScalar::from).collect::<Vec<_>>(),
+            );
+            let before = cs.num_constraints();
+            encrypt(&mut cs.namespace(|| "encrypt"), key, nonce, &msg).unwrap();
+            assert!(cs.is_satisfied());
+            cs.num_constraints() - before
+        };
+        let permutation = {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let vals = alloc(&mut cs, &[ZkScalar::from(0); 4]);
+            crate::poseidon::groth16::poseidon4(
+                &mut cs.namespace(|| "hash"),
+                vals[0].clone(),
+                vals[1].clone(),
+                vals[2].clone(),
+                vals[3].clone(),
+            )
+            .unwrap();
+            // Minus the output constraint
+            cs.num_constraints() - 1
+        };
+        assert_eq!(cost(2) - cost(1), 1);
+        assert_eq!(cost(4) - cost(0), 4 + permutation);
+        assert_eq!(cost(8) - cost(4), 4 + permutation);
+        assert_eq!(cost(5) - cost(4), 1 + permutation);
+    }
+
+    #[test]
+    fn test_decrypt_rejects_wrong_tag_or_key() {
+        let key = BASE_COFACTOR.multiply(&ZkScalar::from(77));
+        let wrong_key = BASE_COFACTOR.multiply(&ZkScalar::from(78));
+        let msg = [ZkScalar::from(1), ZkScalar::from(2)];
+        let (ciphertext, tag) = encryption::encrypt(key, ZkScalar::from(3), &msg);
+        for (key, tag, valid) in [
+            (key, tag, true),
+            (key, tag + ZkScalar::from(1), false),
+            (wrong_key, tag, false),
+        ] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let key = alloc_point(&mut cs.namespace(|| "key"), key);
+            let nonce = alloc(&mut cs.namespace(|| "nonce"), &[ZkScalar::from(3)]).remove(0);
+            let ciphertext = alloc(&mut cs.namespace(|| "ciphertext"), &ciphertext);
+            let tag = alloc(&mut cs.namespace(|| "tag"), &[tag]).remove(0);
+            decrypt(
+                &mut cs.namespace(|| "decrypt"),
+                key,
+                nonce,
+                &ciphertext,
+                tag,
+            )
+            .unwrap();
+            assert_eq!(cs.is_satisfied(), valid);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+#[cfg(feature = "groth16")]
+pub mod groth16;
+
+use crate::commitment::domain_tag;
+use crate::poseidon::{PoseidonSponge, POSEIDON_ARITY4};
+use bazuka::crypto::jubjub::PointAffine;
+use bazuka::zk::ZkScalar;
+
+lazy_static::lazy_static! {
+    pub static ref ENCRYPTION_DOMAIN: ZkScalar = domain_tag("zeekit.encryption");
+}
+
+// Diffie-Hellman shared key, both keys are expected to be in the prime order subgroup
+pub fn shared_key(sk: ZkScalar, pk: PointAffine) -> PointAffine {
+    pk.multiply(&sk)
+}
+
+// Sponge keyed with the shared key, the nonce and the message length
+fn keyed_sponge(key: PointAffine, nonce: ZkScalar, len: usize) -> PoseidonSponge<'static> {
+    let mut sponge = PoseidonSponge::new(&POSEIDON_ARITY4, *ENCRYPTION_DOMAIN);
+    sponge.absorb(&[key.0, key.1, nonce, ZkScalar::from(len as u64)]);
+    sponge
+}
+
+// Duplex encryption, returns the ciphertext and its authentication tag
+// Each block of ciphertext is the message plus squeezed keystream, absorbed back
+// into the sponge before the next block. A (key, nonce) pair should never be reused
+pub fn encrypt(key: PointAffine, nonce: ZkScalar, msg: &[ZkScalar]) -> (Vec<ZkScalar>, ZkScalar) {
+    let mut sponge = keyed_sponge(key, nonce, msg.len());
+    let mut ciphertext = Vec::new();
+    for chunk in msg.chunks(POSEIDON_ARITY4.arity()) {
+        let block = chunk
+            .iter()
+            .map(|m| *m + sponge.squeeze())
+            .collect::<Vec<_>>();
+        sponge.absorb(&block);
+        ciphertext.extend(block);
+    }
+    let tag = sponge.squeeze();
+    (ciphertext, tag)
+}
+
+// None if the tag doesn't match
+pub fn decrypt(
+    key: PointAffine,
+    nonce: ZkScalar,
+    ciphertext: &[ZkScalar],
+    tag: ZkScalar,
+) -> Option<Vec<ZkScalar>> {
+    let mut sponge = keyed_sponge(key, nonce, ciphertext.len());
+    let mut msg = Vec::new();
+    for chunk in ciphertext.chunks(POSEIDON_ARITY4.arity()) {
+        msg.extend(chunk.iter().map(|c| *c - sponge.squeeze()));
+        sponge.absorb(chunk);
+    }
+    (sponge.squeeze() == tag).then_some(msg)
+}
@@ -1,8 +1,15 @@
 #[cfg(feature = "groth16")]
 pub use bls12_381::{Bls12, G1Affine as BellmanG1, G2Affine as BellmanG2, Scalar as BellmanFr};
 
+pub mod commitment;
 pub mod common;
 pub mod eddsa;
+pub mod encryption;
 pub mod merkle;
 pub mod poseidon;
+pub mod poseidon2;
 pub mod reveal;
+pub mod uint;
+
+#[cfg(all(test, feature = "groth16"))]
+mod differential;
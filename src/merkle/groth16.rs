@@ -1,113 +1,607 @@
+use super::{BinaryProof, MultiProof, Proof};
+use crate::common;
 use crate::common::groth16::WrappedLc;
+use crate::poseidon::groth16::{compress, GadgetHasher};
 use crate::BellmanFr;
-use crate::{common, poseidon};
 
+use bazuka::zk::{PoseidonHasher, ZkScalar, ZkStateModel};
 use bellman::gadgets::boolean::{AllocatedBit, Boolean};
 use bellman::gadgets::num::AllocatedNum;
 use bellman::{ConstraintSystem, SynthesisError};
+use ff::Field;
 
-fn merge_hash_poseidon4<CS: ConstraintSystem<BellmanFr>>(
+// Merkle proof of a 4-ary tree with LOG4_TREE_SIZE levels, the depth is fixed by the type
+#[derive(Clone)]
+pub struct AllocatedProof<const LOG4_TREE_SIZE: u8>(Vec<[AllocatedNum<BellmanFr>; 3]>);
+
+impl<const LOG4_TREE_SIZE: u8> AllocatedProof<LOG4_TREE_SIZE> {
+    pub fn alloc<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        proof: Option<Proof<LOG4_TREE_SIZE>>,
+    ) -> Result<Self, SynthesisError> {
+        if let Some(proof) = &proof {
+            if proof.0.len() != LOG4_TREE_SIZE as usize {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+        }
+        let mut levels = Vec::new();
+        for i in 0..LOG4_TREE_SIZE as usize {
+            let level = proof.as_ref().map(|p| p.0[i]);
+            let mut alloc = |j: usize| {
+                AllocatedNum::alloc(
+                    cs.namespace(|| format!("level {} sibling {}", i, j)),
+                    || {
+                        level
+                            .map(|l| l[j].into())
+                            .ok_or(SynthesisError::AssignmentMissing)
+                    },
+                )
+            };
+            levels.push([alloc(0)?, alloc(1)?, alloc(2)?]);
+        }
+        Ok(Self(levels))
+    }
+
+    // Out of siblings that are already allocated, one [_; 3] per level
+    pub fn from_levels(levels: Vec<[AllocatedNum<BellmanFr>; 3]>) -> Result<Self, SynthesisError> {
+        if levels.len() != LOG4_TREE_SIZE as usize {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+        Ok(Self(levels))
+    }
+
+    pub fn levels(&self) -> &[[AllocatedNum<BellmanFr>; 3]] {
+        &self.0
+    }
+}
+
+// Siblings of a MultiProof, the circuit only depends on the number of leaves, their
+// indices are witnesses
+#[derive(Clone)]
+pub struct AllocatedMultiProof<const LOG4_TREE_SIZE: u8, const TOP_LEVELS: u8> {
+    paths: Vec<Vec<[AllocatedNum<BellmanFr>; 3]>>,
+    top: Vec<AllocatedNum<BellmanFr>>,
+}
+
+impl<const LOG4_TREE_SIZE: u8, const TOP_LEVELS: u8>
+    AllocatedMultiProof<LOG4_TREE_SIZE, TOP_LEVELS>
+{
+    pub fn alloc<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        num_leaves: usize,
+        proof: Option<MultiProof<LOG4_TREE_SIZE, TOP_LEVELS>>,
+    ) -> Result<Self, SynthesisError> {
+        let lower = MultiProof::<LOG4_TREE_SIZE, TOP_LEVELS>::lower_levels();
+        let num_top_nodes = MultiProof::<LOG4_TREE_SIZE, TOP_LEVELS>::num_top_nodes();
+        if let Some(proof) = &proof {
+            if proof.paths.len() != num_leaves
+                || proof.paths.iter().any(|p| p.len() != lower)
+                || proof.top.len() != num_top_nodes
+            {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+        }
+        let mut paths = Vec::new();
+        for leaf in 0..num_leaves {
+            let cs = &mut cs.namespace(|| format!("leaf {}", leaf));
+            let mut levels = Vec::new();
+            for i in 0..lower {
+                let level = proof.as_ref().map(|p| p.paths[leaf][i]);
+                let mut alloc = |j: usize| {
+                    AllocatedNum::alloc(
+                        cs.namespace(|| format!("level {} sibling {}", i, j)),
+                        || {
+                            level
+                                .map(|l| l[j].into())
+                                .ok_or(SynthesisError::AssignmentMissing)
+                        },
+                    )
+                };
+                levels.push([alloc(0)?, alloc(1)?, alloc(2)?]);
+            }
+            paths.push(levels);
+        }
+        let top = (0..num_top_nodes)
+            .map(|i| {
+                AllocatedNum::alloc(cs.namespace(|| format!("top {}", i)), || {
+                    proof
+                        .as_ref()
+                        .map(|p| p.top[i].into())
+                        .ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { paths, top })
+    }
+
+    pub fn num_leaves(&self) -> usize {
+        self.paths.len()
+    }
+}
+
+#[derive(Clone)]
+pub struct AllocatedBinaryProof<const DEPTH: u8>(Vec<AllocatedNum<BellmanFr>>);
+
+impl<const DEPTH: u8> AllocatedBinaryProof<DEPTH> {
+    pub fn alloc<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        proof: Option<BinaryProof<DEPTH>>,
+    ) -> Result<Self, SynthesisError> {
+        if let Some(proof) = &proof {
+            if proof.0.len() != DEPTH as usize {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+        }
+        let siblings = (0..DEPTH as usize)
+            .map(|i| {
+                AllocatedNum::alloc(cs.namespace(|| format!("level {} sibling", i)), || {
+                    proof
+                        .as_ref()
+                        .map(|p| p.0[i].into())
+                        .ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self(siblings))
+    }
+}
+
+fn merge_hash<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
     select: (Boolean, Boolean),
-    v: AllocatedNum<BellmanFr>,
+    v: WrappedLc,
     p: [AllocatedNum<BellmanFr>; 3],
 ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
-    let and = Boolean::and(&mut *cs, &select.0, &select.1)?;
-    let or = Boolean::and(&mut *cs, &select.0.not(), &select.1.not())?.not();
-
-    // v0 == s0_or_s1 ? p[0] : v
-    let v0 = common::groth16::mux(
-        &mut *cs,
-        &or,
-        &WrappedLc::alloc_num(v.clone()),
-        &WrappedLc::alloc_num(p[0].clone()),
+    let vals = common::groth16::insert(
+        &mut cs.namespace(|| "insert"),
+        &[select.0, select.1],
+        &v,
+        &p.map(WrappedLc::alloc_num),
     )?;
+    H::hash_lcs_gadget(&mut cs.namespace(|| "hash"), &vals)
+}
 
-    //v1p == s0 ? v : p[0]
-    let v1p = common::groth16::mux(
-        &mut *cs,
-        &select.0,
-        &WrappedLc::alloc_num(p[0].clone()),
-        &WrappedLc::alloc_num(v.clone()),
-    )?;
+// Index is range-checked to exactly 2 * LOG4_TREE_SIZE bits, so that out of range
+// indices can't alias a leaf
+fn index_selectors<CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    index: &WrappedLc,
+) -> Result<Vec<AllocatedBit>, SynthesisError> {
+    common::groth16::lc_to_bits(
+        &mut cs.namespace(|| "index bits"),
+        index,
+        2 * LOG4_TREE_SIZE as usize,
+    )
+}
 
-    //v1 == s1 ? p[1] : v1p
-    let v1 = common::groth16::mux(
-        &mut *cs,
-        &select.1,
-        &WrappedLc::alloc_num(v1p),
-        &WrappedLc::alloc_num(p[1].clone()),
-    )?;
+fn root_from_selectors<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    selectors: &[AllocatedBit],
+    val: WrappedLc,
+    proof: &[[AllocatedNum<BellmanFr>; 3]],
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    let mut curr = val;
+    let mut root = None;
+    for (i, (p, dir)) in proof.iter().zip(selectors.chunks(2)).enumerate() {
+        let node = merge_hash::<H, _>(
+            &mut cs.namespace(|| format!("level {}", i)),
+            (Boolean::Is(dir[0].clone()), Boolean::Is(dir[1].clone())),
+            curr,
+            p.clone(),
+        )?;
+        curr = WrappedLc::alloc_num(node.clone());
+        root = Some(node);
+    }
+    // A tree of a single leaf is the leaf itself
+    root.map_or_else(|| compress(&mut cs.namespace(|| "root"), curr), Ok)
+}
 
-    //v2p == s0 ? p[2] : v
-    let v2p = common::groth16::mux(
-        &mut *cs,
-        &select.0,
-        &WrappedLc::alloc_num(v.clone()),
-        &WrappedLc::alloc_num(p[2].clone()),
-    )?;
+// Root of a 4-ary tree hashed with H, same as ZkStateBuilder<H>
+pub fn calc_root<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    index: AllocatedNum<BellmanFr>,
+    val: AllocatedNum<BellmanFr>,
+    proof: AllocatedProof<LOG4_TREE_SIZE>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    let selectors = index_selectors::<_, LOG4_TREE_SIZE>(&mut *cs, &WrappedLc::alloc_num(index))?;
+    root_from_selectors::<H, _>(cs, &selectors, WrappedLc::alloc_num(val), proof.levels())
+}
+
+pub fn check_proof<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    index: AllocatedNum<BellmanFr>,
+    val: AllocatedNum<BellmanFr>,
+    proof: AllocatedProof<LOG4_TREE_SIZE>,
+    root: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    let new_root = calc_root::<H, _, LOG4_TREE_SIZE>(&mut *cs, index, val, proof)?;
+    common::groth16::assert_equal(cs, enabled, root, new_root)?;
+    Ok(())
+}
 
-    //v2 == s1 ? v2p : p[1]
-    let v2 = common::groth16::mux(
-        &mut *cs,
-        &select.1,
-        &WrappedLc::alloc_num(p[1].clone()),
-        &WrappedLc::alloc_num(v2p),
+// Prove that changing the leaf at index from old_val to new_val turns old_root into
+// new_root, both roots are calculated with the same index bits and siblings
+#[allow(clippy::too_many_arguments)]
+pub fn update_proof<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    index: AllocatedNum<BellmanFr>,
+    old_val: AllocatedNum<BellmanFr>,
+    new_val: AllocatedNum<BellmanFr>,
+    proof: AllocatedProof<LOG4_TREE_SIZE>,
+    old_root: AllocatedNum<BellmanFr>,
+    new_root: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    let selectors = index_selectors::<_, LOG4_TREE_SIZE>(&mut *cs, &WrappedLc::alloc_num(index))?;
+    let calc_old_root = root_from_selectors::<H, _>(
+        &mut cs.namespace(|| "old root"),
+        &selectors,
+        WrappedLc::alloc_num(old_val),
+        proof.levels(),
+    )?;
+    let calc_new_root = root_from_selectors::<H, _>(
+        &mut cs.namespace(|| "new root"),
+        &selectors,
+        WrappedLc::alloc_num(new_val),
+        proof.levels(),
     )?;
+    common::groth16::assert_equal(
+        &mut cs.namespace(|| "old root equal"),
+        enabled.clone(),
+        old_root,
+        calc_old_root,
+    )?;
+    common::groth16::assert_equal(
+        &mut cs.namespace(|| "new root equal"),
+        enabled,
+        new_root,
+        calc_new_root,
+    )?;
+    Ok(())
+}
 
-    //v3 == s0_and_s1 ? v : p[2]
-    let v3 = common::groth16::mux(
-        &mut *cs,
-        &and,
-        &WrappedLc::alloc_num(p[2].clone()),
-        &WrappedLc::alloc_num(v),
+// Prove that the leaf at key is item_type's default value, i.e. key is not in the tree
+pub fn check_non_membership<
+    H: GadgetHasher,
+    CS: ConstraintSystem<BellmanFr>,
+    const LOG4_TREE_SIZE: u8,
+>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    key: AllocatedNum<BellmanFr>,
+    item_type: &ZkStateModel,
+    proof: AllocatedProof<LOG4_TREE_SIZE>,
+    root: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    let default = compress(
+        &mut cs.namespace(|| "default"),
+        WrappedLc::constant::<CS>(item_type.compress_default::<H>().into()),
     )?;
+    check_proof::<H, _, LOG4_TREE_SIZE>(cs, enabled, key, default, proof, root)
+}
 
-    poseidon::groth16::poseidon4(cs, v0, v1, v2, v3)
+// Check the leaves vals at the (Private) indices, all in the 4-ary tree of root
+// The nodes at depth TOP_LEVELS are hashed up to the root once, (4^TOP_LEVELS - 1) / 3
+// hashes, then each leaf costs its index bits, LOG4_TREE_SIZE - TOP_LEVELS levels
+// of a single proof and a 4^TOP_LEVELS - 1 constraint lookup of the top node it
+// reaches, instead of the TOP_LEVELS hashes closest to the root
+pub fn check_multi_proof<
+    H: GadgetHasher,
+    CS: ConstraintSystem<BellmanFr>,
+    const LOG4_TREE_SIZE: u8,
+    const TOP_LEVELS: u8,
+>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    indices: Vec<AllocatedNum<BellmanFr>>,
+    vals: Vec<AllocatedNum<BellmanFr>>,
+    proof: AllocatedMultiProof<LOG4_TREE_SIZE, TOP_LEVELS>,
+    root: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    if indices.len() != proof.num_leaves() || vals.len() != proof.num_leaves() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+    let lower = MultiProof::<LOG4_TREE_SIZE, TOP_LEVELS>::lower_levels();
+    let mut layer = proof.top.clone();
+    let mut level = 0;
+    while layer.len() > 1 {
+        let cs = &mut cs.namespace(|| format!("top level {}", level));
+        layer = layer
+            .chunks(4)
+            .enumerate()
+            .map(|(i, children)| {
+                H::hash_gadget(&mut cs.namespace(|| format!("node {}", i)), children)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        level += 1;
+    }
+    common::groth16::assert_equal(
+        &mut cs.namespace(|| "root equal"),
+        enabled.clone(),
+        root,
+        layer.remove(0),
+    )?;
+    let top = proof
+        .top
+        .into_iter()
+        .map(WrappedLc::alloc_num)
+        .collect::<Vec<_>>();
+    for (i, ((index, val), path)) in indices.into_iter().zip(vals).zip(proof.paths).enumerate() {
+        let cs = &mut cs.namespace(|| format!("leaf {}", i));
+        let selectors =
+            index_selectors::<_, LOG4_TREE_SIZE>(&mut *cs, &WrappedLc::alloc_num(index))?;
+        let node = root_from_selectors::<H, _>(
+            &mut cs.namespace(|| "path"),
+            &selectors[..2 * lower],
+            WrappedLc::alloc_num(val),
+            &path,
+        )?;
+        let top_bits = selectors[2 * lower..]
+            .iter()
+            .cloned()
+            .map(Boolean::Is)
+            .collect::<Vec<_>>();
+        let expected = common::groth16::select(&mut cs.namespace(|| "top"), &top_bits, &top)?;
+        common::groth16::assert_equal(
+            &mut cs.namespace(|| "top equal"),
+            enabled.clone(),
+            expected,
+            node,
+        )?;
+    }
+    Ok(())
 }
 
-pub fn calc_root_poseidon4<CS: ConstraintSystem<BellmanFr>>(
+// Root of a binary tree whose nodes are hashed with H, same as native BinaryTree
+// DEPTH + 1 constraints for the index bits, plus a swap and a hash per level
+pub fn calc_root_binary<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>, const DEPTH: u8>(
     cs: &mut CS,
     index: AllocatedNum<BellmanFr>,
     val: AllocatedNum<BellmanFr>,
-    proof: Vec<[AllocatedNum<BellmanFr>; 3]>,
+    proof: AllocatedBinaryProof<DEPTH>,
 ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
-    let selectors = index.to_bits_le(&mut *cs)?;
-    let mut curr = val.clone();
-    for (p, dir) in proof.into_iter().zip(selectors.chunks(2)) {
-        curr = merge_hash_poseidon4(&mut *cs, (dir[0].clone(), dir[1].clone()), curr, p)?;
+    let selectors =
+        common::groth16::to_bits(&mut cs.namespace(|| "index bits"), index, DEPTH as usize)?;
+    let mut curr = val;
+    for (i, (sibling, dir)) in proof.0.into_iter().zip(selectors).enumerate() {
+        let cs = &mut cs.namespace(|| format!("level {}", i));
+        let (left, right) = common::groth16::conditional_swap(
+            &mut cs.namespace(|| "swap"),
+            &Boolean::Is(dir),
+            &WrappedLc::alloc_num(curr),
+            &WrappedLc::alloc_num(sibling),
+        )?;
+        curr = H::hash_lcs_gadget(&mut cs.namespace(|| "hash"), &[left, right])?;
     }
     Ok(curr)
 }
 
-pub fn check_proof_poseidon4<CS: ConstraintSystem<BellmanFr>>(
+pub fn check_proof_binary<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>, const DEPTH: u8>(
     cs: &mut CS,
     enabled: AllocatedBit,
     index: AllocatedNum<BellmanFr>,
     val: AllocatedNum<BellmanFr>,
-    proof: Vec<[AllocatedNum<BellmanFr>; 3]>,
+    proof: AllocatedBinaryProof<DEPTH>,
     root: AllocatedNum<BellmanFr>,
 ) -> Result<(), SynthesisError> {
-    let new_root = calc_root_poseidon4(&mut *cs, index, val, proof)?;
+    let new_root = calc_root_binary::<H, _, DEPTH>(&mut *cs, index, val, proof)?;
     common::groth16::assert_equal(cs, enabled, root, new_root)?;
     Ok(())
 }
 
+// Siblings on the right of the path of the selectors should be empty subtrees, only
+// enforced when enabled, 8 constraints per level
+fn assert_right_siblings_empty<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    enabled: &AllocatedBit,
+    selectors: &[AllocatedBit],
+    proof: &[[AllocatedNum<BellmanFr>; 3]],
+) -> Result<(), SynthesisError> {
+    let mut default = ZkScalar::zero();
+    for (i, (p, dir)) in proof.iter().zip(selectors.chunks(2)).enumerate() {
+        let cs = &mut cs.namespace(|| format!("level {}", i));
+        let default_fr: BellmanFr = default.into();
+        // Sibling k is on the right when the index digit is <= k
+        let nor = AllocatedBit::nor(cs.namespace(|| "nor"), &dir[0], &dir[1])?;
+        let and = AllocatedBit::and(cs.namespace(|| "and"), &dir[0], &dir[1])?;
+        let on_right = [
+            AllocatedBit::and(cs.namespace(|| "on_right 0"), enabled, &nor)?,
+            AllocatedBit::and_not(cs.namespace(|| "on_right 1"), enabled, &dir[1])?,
+            AllocatedBit::and_not(cs.namespace(|| "on_right 2"), enabled, &and)?,
+        ];
+        for (k, (sibling, on_right)) in p.iter().zip(on_right.iter()).enumerate() {
+            cs.enforce(
+                || format!("on_right * (sibling {} - default) == 0", k),
+                |lc| lc + on_right.get_variable(),
+                |lc| lc + sibling.get_variable() - (default_fr, CS::one()),
+                |lc| lc,
+            );
+        }
+        default = H::hash(&[default; 4]);
+    }
+    Ok(())
+}
+
+// Prove that appending leaves to the tree committed in old_state gives the tree
+// committed in new_state. A state is H(root, size) (Same as IncrementalTree::state
+// when H is PoseidonHasher), so the leaves go at positions old_size, old_size + 1, ...
+// and no position can be skipped. Every leaf comes with its proof in the tree right
+// before it is appended, in which the leaf and everything on the right of it are empty
+#[allow(clippy::too_many_arguments)]
+pub fn append_leaves<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    old_root: AllocatedNum<BellmanFr>,
+    old_size: AllocatedNum<BellmanFr>,
+    leaves: Vec<AllocatedNum<BellmanFr>>,
+    proofs: Vec<AllocatedProof<LOG4_TREE_SIZE>>,
+    old_state: AllocatedNum<BellmanFr>,
+    new_state: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    if leaves.len() != proofs.len() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+    let calc_old_state = H::hash_lcs_gadget(
+        &mut cs.namespace(|| "old state"),
+        &[
+            WrappedLc::alloc_num(old_root.clone()),
+            WrappedLc::alloc_num(old_size.clone()),
+        ],
+    )?;
+    common::groth16::assert_equal(
+        &mut cs.namespace(|| "old state equal"),
+        enabled.clone(),
+        old_state,
+        calc_old_state,
+    )?;
+    let mut curr_root = old_root;
+    let mut leaf_index = WrappedLc::alloc_num(old_size);
+    for (i, (leaf, proof)) in leaves.into_iter().zip(proofs).enumerate() {
+        let cs = &mut cs.namespace(|| format!("leaf {}", i));
+        let selectors = index_selectors::<_, LOG4_TREE_SIZE>(&mut *cs, &leaf_index)?;
+        assert_right_siblings_empty::<H, _>(
+            &mut cs.namespace(|| "right siblings"),
+            &enabled,
+            &selectors,
+            proof.levels(),
+        )?;
+        let calc_old_root = root_from_selectors::<H, _>(
+            &mut cs.namespace(|| "old root"),
+            &selectors,
+            WrappedLc::zero(),
+            proof.levels(),
+        )?;
+        let calc_new_root = root_from_selectors::<H, _>(
+            &mut cs.namespace(|| "new root"),
+            &selectors,
+            WrappedLc::alloc_num(leaf),
+            proof.levels(),
+        )?;
+        common::groth16::assert_equal(
+            &mut cs.namespace(|| "old root equal"),
+            enabled.clone(),
+            curr_root,
+            calc_old_root,
+        )?;
+        curr_root = calc_new_root;
+        leaf_index.add_constant::<CS>(BellmanFr::one());
+    }
+    let calc_new_state = H::hash_lcs_gadget(
+        &mut cs.namespace(|| "new state"),
+        &[WrappedLc::alloc_num(curr_root), leaf_index],
+    )?;
+    common::groth16::assert_equal(
+        &mut cs.namespace(|| "new state equal"),
+        enabled,
+        new_state,
+        calc_new_state,
+    )?;
+    Ok(())
+}
+
+pub fn calc_root_poseidon4<CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    index: AllocatedNum<BellmanFr>,
+    val: AllocatedNum<BellmanFr>,
+    proof: AllocatedProof<LOG4_TREE_SIZE>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    calc_root::<PoseidonHasher, _, LOG4_TREE_SIZE>(cs, index, val, proof)
+}
+
+pub fn check_proof_poseidon4<CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    index: AllocatedNum<BellmanFr>,
+    val: AllocatedNum<BellmanFr>,
+    proof: AllocatedProof<LOG4_TREE_SIZE>,
+    root: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    check_proof::<PoseidonHasher, _, LOG4_TREE_SIZE>(cs, enabled, index, val, proof, root)
+}
+
+pub fn check_non_membership_poseidon4<CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    key: AllocatedNum<BellmanFr>,
+    item_type: &ZkStateModel,
+    proof: AllocatedProof<LOG4_TREE_SIZE>,
+    root: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    check_non_membership::<PoseidonHasher, _, LOG4_TREE_SIZE>(
+        cs, enabled, key, item_type, proof, root,
+    )
+}
+
+pub fn check_multi_proof_poseidon4<
+    CS: ConstraintSystem<BellmanFr>,
+    const LOG4_TREE_SIZE: u8,
+    const TOP_LEVELS: u8,
+>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    indices: Vec<AllocatedNum<BellmanFr>>,
+    vals: Vec<AllocatedNum<BellmanFr>>,
+    proof: AllocatedMultiProof<LOG4_TREE_SIZE, TOP_LEVELS>,
+    root: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    check_multi_proof::<PoseidonHasher, _, LOG4_TREE_SIZE, TOP_LEVELS>(
+        cs, enabled, indices, vals, proof, root,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn append_leaves_poseidon4<CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    old_root: AllocatedNum<BellmanFr>,
+    old_size: AllocatedNum<BellmanFr>,
+    leaves: Vec<AllocatedNum<BellmanFr>>,
+    proofs: Vec<AllocatedProof<LOG4_TREE_SIZE>>,
+    old_state: AllocatedNum<BellmanFr>,
+    new_state: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    append_leaves::<PoseidonHasher, _, LOG4_TREE_SIZE>(
+        cs, enabled, old_root, old_size, leaves, proofs, old_state, new_state,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_proof_poseidon4<CS: ConstraintSystem<BellmanFr>, const LOG4_TREE_SIZE: u8>(
+    cs: &mut CS,
+    enabled: AllocatedBit,
+    index: AllocatedNum<BellmanFr>,
+    old_val: AllocatedNum<BellmanFr>,
+    new_val: AllocatedNum<BellmanFr>,
+    proof: AllocatedProof<LOG4_TREE_SIZE>,
+    old_root: AllocatedNum<BellmanFr>,
+    new_root: AllocatedNum<BellmanFr>,
+) -> Result<(), SynthesisError> {
+    update_proof::<PoseidonHasher, _, LOG4_TREE_SIZE>(
+        cs, enabled, index, old_val, new_val, proof, old_root, new_root,
+    )
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::poseidon::PoseidonArity2Hasher;
+    use crate::poseidon2::Poseidon2Hasher;
     use crate::Bls12;
-    use bazuka::zk::{PoseidonHasher, ZkDataLocator, ZkScalar, ZkStateBuilder, ZkStateModel};
+    use bazuka::zk::{
+        PoseidonHasher, ZkDataLocator, ZkHasher, ZkScalar, ZkStateBuilder, ZkStateModel,
+    };
     use bellman::gadgets::num::AllocatedNum;
+    use bellman::gadgets::test::TestConstraintSystem;
     use bellman::{groth16, Circuit, ConstraintSystem, SynthesisError};
-    use ff::Field;
+    use ff::{Field, PrimeField};
     use rand::rngs::OsRng;
 
     struct TestPoseidon4MerkleProofCircuit {
         index: Option<BellmanFr>,
         val: Option<BellmanFr>,
         root: Option<BellmanFr>,
-        proof: Vec<[Option<BellmanFr>; 3]>,
+        proof: Option<Proof<4>>,
     }
 
     impl Circuit<BellmanFr> for TestPoseidon4MerkleProofCircuit {
@@ -128,20 +622,7 @@ mod test {
             val.inputize(&mut *cs)?;
             root.inputize(&mut *cs)?;
 
-            let mut proof = Vec::new();
-            for p in self.proof {
-                proof.push([
-                    AllocatedNum::alloc(&mut *cs, || {
-                        p[0].ok_or(SynthesisError::AssignmentMissing)
-                    })?,
-                    AllocatedNum::alloc(&mut *cs, || {
-                        p[1].ok_or(SynthesisError::AssignmentMissing)
-                    })?,
-                    AllocatedNum::alloc(&mut *cs, || {
-                        p[2].ok_or(SynthesisError::AssignmentMissing)
-                    })?,
-                ]);
-            }
+            let proof = AllocatedProof::alloc(&mut *cs, self.proof)?;
 
             let enabled = AllocatedBit::alloc(&mut *cs, Some(true))?;
 
@@ -157,7 +638,7 @@ mod test {
             let c = TestPoseidon4MerkleProofCircuit {
                 index: None,
                 val: None,
-                proof: vec![[None; 3]; 4],
+                proof: None,
                 root: None,
             };
             groth16::generate_random_parameters::<Bls12, _, _>(c, &mut OsRng).unwrap()
@@ -176,12 +657,8 @@ mod test {
                 .unwrap();
         }
         for i in 0..256 {
-            let proof: Vec<[Option<BellmanFr>; 3]> = builder
-                .prove(ZkDataLocator(vec![]), i)
-                .unwrap()
-                .into_iter()
-                .map(|p| [Some(p[0].into()), Some(p[1].into()), Some(p[2].into())])
-                .collect();
+            let proof =
+                Proof::<4>::try_from(builder.prove(ZkDataLocator(vec![]), i).unwrap()).unwrap();
 
             let index = ZkScalar::from(i as u64);
             let val = ZkScalar::from(i as u64);
@@ -190,7 +667,7 @@ mod test {
             let c = TestPoseidon4MerkleProofCircuit {
                 index: Some(index.into()),
                 val: Some(val.into()),
-                proof,
+                proof: Some(proof),
                 root: Some(root.into()),
             };
             let proof = groth16::create_random_proof(c, &params, &mut OsRng).unwrap();
@@ -207,4 +684,692 @@ mod test {
             .is_ok());
         }
     }
+
+    #[test]
+    fn test_poseidon2_merkle_proofs() {
+        let model = ZkStateModel::List {
+            log4_size: 3,
+            item_type: Box::new(ZkStateModel::Scalar),
+        };
+        let mut builder = ZkStateBuilder::<Poseidon2Hasher>::new(model);
+        for i in 0..64 {
+            builder
+                .set(ZkDataLocator(vec![i]), ZkScalar::from(i as u64 * 3))
+                .unwrap();
+        }
+        let root = builder.get(ZkDataLocator(vec![])).unwrap();
+        for i in [0, 1, 6, 27, 63] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let alloc = |cs: &mut TestConstraintSystem<BellmanFr>, name: String, v: ZkScalar| {
+                AllocatedNum::alloc(cs.namespace(|| name), || Ok(v.into())).unwrap()
+            };
+            let index = alloc(&mut cs, "index".into(), ZkScalar::from(i as u64));
+            let val = alloc(&mut cs, "val".into(), ZkScalar::from(i as u64 * 3));
+            let root_num = alloc(&mut cs, "root".into(), root);
+            let proof =
+                Proof::<3>::try_from(builder.prove(ZkDataLocator(vec![]), i).unwrap()).unwrap();
+            let proof = AllocatedProof::alloc(&mut cs.namespace(|| "proof"), Some(proof)).unwrap();
+            let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(true)).unwrap();
+            check_proof::<Poseidon2Hasher, _, 3>(
+                &mut cs.namespace(|| "check"),
+                enabled,
+                index,
+                val,
+                proof,
+                root_num,
+            )
+            .unwrap();
+            assert!(cs.is_satisfied());
+
+            cs.set("val/num", BellmanFr::from(1000));
+            assert!(!cs.is_satisfied());
+        }
+    }
+
+    #[test]
+    fn test_merge_hash() {
+        let nums = |cs: &mut TestConstraintSystem<BellmanFr>, vals: &[u64]| {
+            vals.iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    AllocatedNum::alloc(cs.namespace(|| format!("val {}", i)), || {
+                        Ok(BellmanFr::from(*v))
+                    })
+                    .unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let vals = nums(&mut cs, &[1, 2, 3, 4]);
+        crate::poseidon::groth16::poseidon(&mut cs, &vals).unwrap();
+        let hash_constraints = cs.num_constraints();
+
+        for index in 0..4 {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let bits = (0..2)
+                .map(|i| {
+                    let bit = (index >> i) & 1 == 1;
+                    Boolean::Is(
+                        AllocatedBit::alloc(cs.namespace(|| format!("bit {}", i)), Some(bit))
+                            .unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let vals = nums(&mut cs, &[7, 1, 2, 3]);
+            let before = cs.num_constraints();
+            let out = merge_hash::<PoseidonHasher, _>(
+                &mut cs,
+                (bits[0].clone(), bits[1].clone()),
+                WrappedLc::alloc_num(vals[0].clone()),
+                [vals[1].clone(), vals[2].clone(), vals[3].clone()],
+            )
+            .unwrap();
+            let mut expected = vec![1, 2, 3];
+            expected.insert(index, 7);
+            let expected = expected.into_iter().map(ZkScalar::from).collect::<Vec<_>>();
+            assert_eq!(
+                out.get_value(),
+                Some(PoseidonHasher::hash(&expected).into())
+            );
+            assert!(cs.is_satisfied());
+            assert_eq!(cs.num_constraints() - before, hash_constraints + 5);
+        }
+    }
+
+    #[test]
+    fn test_proof_depth() {
+        let model = ZkStateModel::List {
+            log4_size: 2,
+            item_type: Box::new(ZkStateModel::Scalar),
+        };
+        let builder = ZkStateBuilder::<PoseidonHasher>::new(model);
+        let proof = builder.prove(ZkDataLocator(vec![]), 5).unwrap();
+        assert!(Proof::<2>::try_from(proof.clone()).is_ok());
+        assert_eq!(Proof::<3>::try_from(proof.clone()).unwrap_err(), proof);
+
+        // Proofs built by hand are checked when allocated
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        assert!(matches!(
+            AllocatedProof::<3>::alloc(&mut cs, Some(Proof(proof))),
+            Err(SynthesisError::Unsatisfiable)
+        ));
+        let proof = AllocatedProof::<3>::alloc(&mut cs, Some(Proof::default())).unwrap();
+        assert_eq!(proof.levels().len(), 3);
+    }
+
+    #[test]
+    fn test_aliased_index_is_rejected() {
+        let model = ZkStateModel::List {
+            log4_size: 2,
+            item_type: Box::new(ZkStateModel::Scalar),
+        };
+        let mut builder = ZkStateBuilder::<PoseidonHasher>::new(model);
+        for i in 0..16 {
+            builder
+                .set(ZkDataLocator(vec![i]), ZkScalar::from(i as u64 + 10))
+                .unwrap();
+        }
+        let root = builder.get(ZkDataLocator(vec![])).unwrap();
+        let proof = Proof::<2>::try_from(builder.prove(ZkDataLocator(vec![]), 7).unwrap()).unwrap();
+
+        // Same low bits as leaf 7, but out of the tree's index range
+        let aliases = [
+            ZkScalar::from(7 + 16),
+            ZkScalar::from(7 + (1 << 40)),
+            ZkScalar::from(7) - ZkScalar::from(16),
+        ];
+        for (index, valid) in [(ZkScalar::from(7), true)]
+            .into_iter()
+            .chain(aliases.into_iter().map(|a| (a, false)))
+        {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let index = AllocatedNum::alloc(cs.namespace(|| "index"), || Ok(index.into())).unwrap();
+            let val =
+                AllocatedNum::alloc(cs.namespace(|| "val"), || Ok(BellmanFr::from(17))).unwrap();
+            let root = AllocatedNum::alloc(cs.namespace(|| "root"), || Ok(root.into())).unwrap();
+            let proof =
+                AllocatedProof::alloc(&mut cs.namespace(|| "proof"), Some(proof.clone())).unwrap();
+            let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(true)).unwrap();
+            check_proof_poseidon4(
+                &mut cs.namespace(|| "check"),
+                enabled,
+                index,
+                val,
+                proof,
+                root,
+            )
+            .unwrap();
+            assert_eq!(cs.is_satisfied(), valid);
+        }
+    }
+
+    #[test]
+    fn test_update_proof() {
+        let model = ZkStateModel::List {
+            log4_size: 3,
+            item_type: Box::new(ZkStateModel::Scalar),
+        };
+        let mut builder = ZkStateBuilder::<PoseidonHasher>::new(model);
+        for i in 0..64 {
+            builder
+                .set(ZkDataLocator(vec![i]), ZkScalar::from(i as u64 * 5))
+                .unwrap();
+        }
+        for i in [0, 13, 42, 63] {
+            let old_root = builder.get(ZkDataLocator(vec![])).unwrap();
+            let old_val = builder.get(ZkDataLocator(vec![i])).unwrap();
+            let proof =
+                Proof::<3>::try_from(builder.prove(ZkDataLocator(vec![]), i).unwrap()).unwrap();
+            let new_val = ZkScalar::from(1000 + i as u64);
+            builder.set(ZkDataLocator(vec![i]), new_val).unwrap();
+            let new_root = builder.get(ZkDataLocator(vec![])).unwrap();
+
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let mut alloc = |name: &str, v: ZkScalar| {
+                AllocatedNum::alloc(cs.namespace(|| name), || Ok(v.into())).unwrap()
+            };
+            let index = alloc("index", ZkScalar::from(i as u64));
+            let old_val = alloc("old val", old_val);
+            let new_val = alloc("new val", new_val);
+            let old_root = alloc("old root", old_root);
+            let new_root = alloc("new root", new_root);
+            let proof = AllocatedProof::alloc(&mut cs.namespace(|| "proof"), Some(proof)).unwrap();
+            let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(true)).unwrap();
+            update_proof_poseidon4(
+                &mut cs.namespace(|| "update"),
+                enabled,
+                index,
+                old_val,
+                new_val,
+                proof,
+                old_root,
+                new_root,
+            )
+            .unwrap();
+            assert!(cs.is_satisfied());
+
+            // Only the proven leaf may change
+            let other_index = BellmanFr::from((i as u64 + 1) % 64);
+            cs.set("index/num", other_index);
+            assert!(!cs.is_satisfied());
+            cs.set("index/num", BellmanFr::from(i as u64));
+            cs.set("new val/num", BellmanFr::from(1));
+            assert!(!cs.is_satisfied());
+        }
+    }
+
+    #[test]
+    fn test_non_membership() {
+        let item_type = ZkStateModel::Struct {
+            field_types: vec![ZkStateModel::Scalar, ZkStateModel::Scalar],
+        };
+        let model = ZkStateModel::List {
+            log4_size: 2,
+            item_type: Box::new(item_type.clone()),
+        };
+        let mut builder = ZkStateBuilder::<PoseidonHasher>::new(model);
+        for i in [1, 5, 6] {
+            builder
+                .set(ZkDataLocator(vec![i, 1]), ZkScalar::from(i as u64 + 1))
+                .unwrap();
+        }
+        let root = builder.get(ZkDataLocator(vec![])).unwrap();
+        for key in 0..16 {
+            let proof = crate::merkle::non_membership_proof::<_, 2>(
+                &builder,
+                ZkDataLocator(vec![]),
+                &item_type,
+                key,
+            );
+            let is_set = [1, 5, 6].contains(&key);
+            assert_eq!(proof.is_none(), is_set);
+
+            // A membership proof of a set key can't be passed off as non-membership
+            let proof = proof.unwrap_or_else(|| {
+                Proof::try_from(builder.prove(ZkDataLocator(vec![]), key).unwrap()).unwrap()
+            });
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let key_num =
+                AllocatedNum::alloc(cs.namespace(|| "key"), || Ok(BellmanFr::from(key as u64)))
+                    .unwrap();
+            let root = AllocatedNum::alloc(cs.namespace(|| "root"), || Ok(root.into())).unwrap();
+            let proof = AllocatedProof::alloc(&mut cs.namespace(|| "proof"), Some(proof)).unwrap();
+            let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(true)).unwrap();
+            check_non_membership_poseidon4(
+                &mut cs.namespace(|| "non membership"),
+                enabled,
+                key_num,
+                &item_type,
+                proof,
+                root,
+            )
+            .unwrap();
+            assert_eq!(cs.is_satisfied(), !is_set);
+        }
+    }
+
+    const NUM_MULTI_LEAVES: usize = 8;
+
+    // Leaves at private indices, only the root is public
+    struct TestMultiProofCircuit {
+        indices: Option<Vec<u32>>,
+        vals: Option<Vec<ZkScalar>>,
+        root: Option<BellmanFr>,
+        proof: Option<MultiProof<3, 2>>,
+    }
+
+    impl Circuit<BellmanFr> for TestMultiProofCircuit {
+        fn synthesize<CS: ConstraintSystem<BellmanFr>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<(), SynthesisError> {
+            let mut alloc = |name: String, v: Option<BellmanFr>| {
+                AllocatedNum::alloc(cs.namespace(|| name), || {
+                    v.ok_or(SynthesisError::AssignmentMissing)
+                })
+            };
+            let mut indices = Vec::new();
+            let mut vals = Vec::new();
+            for i in 0..NUM_MULTI_LEAVES {
+                indices.push(alloc(
+                    format!("index {}", i),
+                    self.indices
+                        .as_ref()
+                        .map(|ids| BellmanFr::from(ids[i] as u64)),
+                )?);
+                vals.push(alloc(
+                    format!("val {}", i),
+                    self.vals.as_ref().map(|v| v[i].into()),
+                )?);
+            }
+            let root = alloc("root".into(), self.root)?;
+            root.inputize(cs.namespace(|| "root input"))?;
+            let proof = AllocatedMultiProof::alloc(
+                &mut cs.namespace(|| "proof"),
+                NUM_MULTI_LEAVES,
+                self.proof,
+            )?;
+            let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(true))?;
+            check_multi_proof_poseidon4(
+                &mut cs.namespace(|| "multi"),
+                enabled,
+                indices,
+                vals,
+                proof,
+                root,
+            )
+        }
+    }
+
+    #[test]
+    fn test_multi_proof() {
+        let model = ZkStateModel::List {
+            log4_size: 3,
+            item_type: Box::new(ZkStateModel::Scalar),
+        };
+        let mut builder = ZkStateBuilder::<PoseidonHasher>::new(model);
+        for i in 0..64 {
+            builder
+                .set(ZkDataLocator(vec![i]), ZkScalar::from(i as u64 * 7))
+                .unwrap();
+        }
+        let root = builder.get(ZkDataLocator(vec![])).unwrap();
+        let circuit = |indices: &[u32]| {
+            let vals = indices
+                .iter()
+                .map(|i| ZkScalar::from(*i as u64 * 7))
+                .collect::<Vec<_>>();
+            let proof = MultiProof::<3, 2>::build(&builder, ZkDataLocator(vec![]), indices);
+            let proof = proof.unwrap();
+            assert_eq!(proof.root::<PoseidonHasher>(indices, &vals), Some(root));
+            TestMultiProofCircuit {
+                indices: Some(indices.to_vec()),
+                vals: Some(vals),
+                root: Some(root.into()),
+                proof: Some(proof),
+            }
+        };
+        // Leaves anywhere in the tree, in any order and possibly repeated
+        let index_sets: [[u32; NUM_MULTI_LEAVES]; 2] =
+            [[3, 0, 17, 40, 63, 61, 17, 62], [5, 6, 7, 8, 9, 10, 11, 12]];
+
+        assert!(MultiProof::<3, 2>::build(&builder, ZkDataLocator(vec![]), &[64]).is_none());
+        let proof = MultiProof::<3, 2>::build(&builder, ZkDataLocator(vec![]), &[3]).unwrap();
+        assert_eq!(proof.top.len(), 16);
+        assert_eq!(proof.paths[0].len(), 1);
+        assert_eq!(
+            proof.root::<PoseidonHasher>(&[3], &[ZkScalar::from(21)]),
+            Some(root)
+        );
+        assert_eq!(
+            proof.root::<PoseidonHasher>(&[3], &[ZkScalar::from(22)]),
+            None
+        );
+        assert_eq!(
+            proof.root::<PoseidonHasher>(&[7], &[ZkScalar::from(21)]),
+            None
+        );
+
+        // A single circuit (And trusted setup) for every set of indices
+        let params = groth16::generate_random_parameters::<Bls12, _, _>(
+            TestMultiProofCircuit {
+                indices: None,
+                vals: None,
+                root: None,
+                proof: None,
+            },
+            &mut OsRng,
+        )
+        .unwrap();
+        let pvk = groth16::prepare_verifying_key(&params.vk);
+        for indices in index_sets {
+            let proof =
+                groth16::create_random_proof(circuit(&indices), &params, &mut OsRng).unwrap();
+            assert!(groth16::verify_proof(&pvk, &proof, &[root.into()]).is_ok());
+            assert!(groth16::verify_proof(&pvk, &proof, &[root.double().into()]).is_err());
+        }
+
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        circuit(&index_sets[0]).synthesize(&mut cs).unwrap();
+        assert!(cs.is_satisfied());
+        let multi_constraints = cs.num_constraints();
+        // Another value, or another index for the same value
+        cs.set("val 4/num", BellmanFr::from(1));
+        assert!(!cs.is_satisfied());
+        cs.set("val 4/num", BellmanFr::from(63 * 7));
+        assert!(cs.is_satisfied());
+        cs.set("index 4/num", BellmanFr::from(62));
+        assert!(!cs.is_satisfied());
+
+        // Cheaper than single proofs of the same private indices
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        for (i, index) in index_sets[0].iter().enumerate() {
+            let cs = &mut cs.namespace(|| format!("single {}", i));
+            let proof = Proof::<3>::try_from(builder.prove(ZkDataLocator(vec![]), *index).unwrap())
+                .unwrap();
+            let mut alloc = |name: &str, v: ZkScalar| {
+                AllocatedNum::alloc(cs.namespace(|| name), || Ok(v.into())).unwrap()
+            };
+            let index_num = alloc("index", ZkScalar::from(*index as u64));
+            let val = alloc("val", ZkScalar::from(*index as u64 * 7));
+            let root_num = alloc("root", root);
+            let proof = AllocatedProof::alloc(&mut cs.namespace(|| "proof"), Some(proof)).unwrap();
+            let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(true)).unwrap();
+            check_proof_poseidon4(
+                &mut cs.namespace(|| "check"),
+                enabled,
+                index_num,
+                val,
+                proof,
+                root_num,
+            )
+            .unwrap();
+        }
+        assert!(cs.is_satisfied());
+        assert!(multi_constraints < cs.num_constraints());
+    }
+
+    #[test]
+    fn test_binary_merkle_proofs() {
+        let mut tree = crate::merkle::BinaryTree::<PoseidonArity2Hasher, 5>::new();
+        let empty_root = tree.root();
+        for i in [0, 3, 7, 8, 30] {
+            tree.set(i, ZkScalar::from(i * 11 + 1));
+        }
+        assert_ne!(tree.root(), empty_root);
+        let root = tree.root();
+        for i in [0, 1, 7, 8, 30, 31] {
+            let proof = tree.prove(i);
+            assert_eq!(proof.root::<PoseidonArity2Hasher>(i, tree.get(i)), root);
+
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let mut alloc = |name: &str, v: ZkScalar| {
+                AllocatedNum::alloc(cs.namespace(|| name), || Ok(v.into())).unwrap()
+            };
+            let index = alloc("index", ZkScalar::from(i));
+            let val = alloc("val", tree.get(i));
+            let root_num = alloc("root", root);
+            let proof =
+                AllocatedBinaryProof::alloc(&mut cs.namespace(|| "proof"), Some(proof)).unwrap();
+            let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(true)).unwrap();
+            check_proof_binary::<PoseidonArity2Hasher, _, 5>(
+                &mut cs.namespace(|| "check"),
+                enabled,
+                index,
+                val,
+                proof,
+                root_num,
+            )
+            .unwrap();
+            assert!(cs.is_satisfied());
+
+            // Sibling's index, and an alias of the index out of the tree
+            cs.set("index/num", BellmanFr::from(i ^ 1));
+            assert!(!cs.is_satisfied());
+            cs.set("index/num", BellmanFr::from(i + 32));
+            assert!(!cs.is_satisfied());
+        }
+    }
+
+    #[test]
+    fn test_binary_tree_reference_roots() {
+        // Roots from the reference poseidonperm_x5_255_3 permutation, hashing a node as
+        // the first rate element of the permutation of [0, left, right]
+        let mut tree = crate::merkle::BinaryTree::<PoseidonArity2Hasher, 3>::new();
+        assert_eq!(
+            tree.root(),
+            ZkScalar::from_str_vartime(
+                "34197074458905013199929618475618103933141459572533842883143596724527593161482"
+            )
+            .unwrap()
+        );
+        tree.set(0, ZkScalar::from(1));
+        tree.set(5, ZkScalar::from(2));
+        assert_eq!(
+            tree.root(),
+            ZkScalar::from_str_vartime(
+                "1365937851120283562765212108403600363806721169258490536710955314382466858970"
+            )
+            .unwrap()
+        );
+
+        // Empty subtrees follow the default leaf
+        let tree = crate::merkle::BinaryTree::<PoseidonArity2Hasher, 3>::with_default_leaf(
+            ZkScalar::from(1),
+        );
+        let mut expected = ZkScalar::from(1);
+        for _ in 0..3 {
+            expected = PoseidonArity2Hasher::hash(&[expected, expected]);
+        }
+        assert_eq!(tree.root(), expected);
+    }
+
+    #[test]
+    fn test_incremental_tree() {
+        let model = ZkStateModel::List {
+            log4_size: 2,
+            item_type: Box::new(ZkStateModel::Scalar),
+        };
+        let mut builder = ZkStateBuilder::<PoseidonHasher>::new(model);
+        let mut tree = crate::merkle::IncrementalTree::<2>::new();
+        assert_eq!(tree.root(), builder.get(ZkDataLocator(vec![])).unwrap());
+        for i in 0..16 {
+            let proof = tree.next_proof().unwrap();
+            assert_eq!(
+                proof.0,
+                builder.prove(ZkDataLocator(vec![]), i as u32).unwrap()
+            );
+            let leaf = ZkScalar::from(i * 3 + 1);
+            assert_eq!(tree.append(leaf), Some(i));
+            builder.set(ZkDataLocator(vec![i as u32]), leaf).unwrap();
+            assert_eq!(tree.root(), builder.get(ZkDataLocator(vec![])).unwrap());
+        }
+        assert_eq!(tree.size(), 16);
+        assert!(tree.next_proof().is_none());
+        assert_eq!(tree.append(ZkScalar::one()), None);
+    }
+
+    fn check_append(
+        enabled: bool,
+        old_root: ZkScalar,
+        old_size: u64,
+        leaves: &[ZkScalar],
+        proofs: &[Proof<2>],
+        old_state: ZkScalar,
+        new_state: ZkScalar,
+    ) -> TestConstraintSystem<BellmanFr> {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let mut alloc = |name: String, v: ZkScalar| {
+            AllocatedNum::alloc(cs.namespace(|| name), || Ok(v.into())).unwrap()
+        };
+        let old_root = alloc("old root".into(), old_root);
+        let old_size = alloc("old size".into(), ZkScalar::from(old_size));
+        let old_state = alloc("old state".into(), old_state);
+        let new_state = alloc("new state".into(), new_state);
+        let leaf_nums = leaves
+            .iter()
+            .enumerate()
+            .map(|(i, l)| alloc(format!("leaf {}", i), *l))
+            .collect();
+        let proof_nums = proofs
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                AllocatedProof::alloc(
+                    &mut cs.namespace(|| format!("proof {}", i)),
+                    Some(p.clone()),
+                )
+                .unwrap()
+            })
+            .collect();
+        let enabled = AllocatedBit::alloc(cs.namespace(|| "enabled"), Some(enabled)).unwrap();
+        append_leaves_poseidon4(
+            &mut cs.namespace(|| "append"),
+            enabled,
+            old_root,
+            old_size,
+            leaf_nums,
+            proof_nums,
+            old_state,
+            new_state,
+        )
+        .unwrap();
+        cs
+    }
+
+    #[test]
+    fn test_append_leaves() {
+        let mut tree = crate::merkle::IncrementalTree::<2>::new();
+        for i in 0..5 {
+            tree.append(ZkScalar::from(i + 100)).unwrap();
+        }
+        let (old_root, old_state) = (tree.root(), tree.state());
+        let leaves = [ZkScalar::from(7), ZkScalar::from(8), ZkScalar::from(9)];
+        let mut proofs = Vec::new();
+        for leaf in leaves {
+            proofs.push(tree.next_proof().unwrap());
+            tree.append(leaf).unwrap();
+        }
+        let new_state = tree.state();
+
+        for (old_size, valid) in [(5, true), (4, false), (6, false)] {
+            let cs = check_append(
+                true, old_root, old_size, &leaves, &proofs, old_state, new_state,
+            );
+            assert_eq!(cs.is_satisfied(), valid);
+        }
+        // The new state commits to the new size
+        let wrong_size = PoseidonHasher::hash(&[tree.root(), ZkScalar::from(9)]);
+        let cs = check_append(true, old_root, 5, &leaves, &proofs, old_state, wrong_size);
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_append_disabled_with_garbage() {
+        // Filled right siblings, a wrong size and unrelated states
+        let garbage = Proof::<2>(vec![[ZkScalar::from(3); 3]; 2]);
+        let (leaves, proofs) = ([ZkScalar::from(7)], [garbage]);
+        let vals = [1, 2, 3].map(ZkScalar::from);
+        assert!(check_append(false, vals[0], 9, &leaves, &proofs, vals[1], vals[2]).is_satisfied());
+        assert!(!check_append(true, vals[0], 9, &leaves, &proofs, vals[1], vals[2]).is_satisfied());
+    }
+
+    #[test]
+    fn test_append_rejects_skipped_position() {
+        // Honest proofs of appending at 6 in a tree of 5 leaves, which would leave 5 empty
+        let model = ZkStateModel::List {
+            log4_size: 2,
+            item_type: Box::new(ZkStateModel::Scalar),
+        };
+        let mut builder = ZkStateBuilder::<PoseidonHasher>::new(model);
+        let mut tree = crate::merkle::IncrementalTree::<2>::new();
+        for i in 0..5 {
+            builder
+                .set(ZkDataLocator(vec![i]), ZkScalar::from(i as u64 + 100))
+                .unwrap();
+            tree.append(ZkScalar::from(i as u64 + 100)).unwrap();
+        }
+        let old_root = builder.get(ZkDataLocator(vec![])).unwrap();
+        let leaves = [ZkScalar::from(7), ZkScalar::from(8)];
+        let mut proofs = Vec::new();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let index = 6 + i as u32;
+            proofs.push(
+                Proof::<2>::try_from(builder.prove(ZkDataLocator(vec![]), index).unwrap()).unwrap(),
+            );
+            builder.set(ZkDataLocator(vec![index]), *leaf).unwrap();
+        }
+        let new_root = builder.get(ZkDataLocator(vec![])).unwrap();
+        let new_state = PoseidonHasher::hash(&[new_root, ZkScalar::from(8)]);
+
+        // Only the committed size stops it, the proofs themselves are consistent
+        let forged_state = PoseidonHasher::hash(&[old_root, ZkScalar::from(6)]);
+        assert!(
+            check_append(true, old_root, 6, &leaves, &proofs, forged_state, new_state)
+                .is_satisfied()
+        );
+        let cs = check_append(true, old_root, 6, &leaves, &proofs, tree.state(), new_state);
+        assert!(!cs.is_satisfied());
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some("append/old state equal/enabled * b == enabled_in_a")
+        );
+    }
+
+    #[test]
+    fn test_append_rejects_filled_right_siblings() {
+        // Leaf 1 is set, so appending at 0 would overwrite the tree on its right
+        let model = ZkStateModel::List {
+            log4_size: 2,
+            item_type: Box::new(ZkStateModel::Scalar),
+        };
+        let mut builder = ZkStateBuilder::<PoseidonHasher>::new(model);
+        builder
+            .set(ZkDataLocator(vec![1]), ZkScalar::from(5))
+            .unwrap();
+        let old_root = builder.get(ZkDataLocator(vec![])).unwrap();
+        let proof = Proof::<2>::try_from(builder.prove(ZkDataLocator(vec![]), 0).unwrap()).unwrap();
+        builder
+            .set(ZkDataLocator(vec![0]), ZkScalar::from(6))
+            .unwrap();
+        let new_root = builder.get(ZkDataLocator(vec![])).unwrap();
+
+        // States claiming the tree was empty
+        let old_state = PoseidonHasher::hash(&[old_root, ZkScalar::zero()]);
+        let new_state = PoseidonHasher::hash(&[new_root, ZkScalar::from(1)]);
+        let cs = check_append(
+            true,
+            old_root,
+            0,
+            &[ZkScalar::from(6)],
+            &[proof],
+            old_state,
+            new_state,
+        );
+        assert!(!cs.is_satisfied());
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some("append/leaf 0/right siblings/level 0/on_right * (sibling 0 - default) == 0")
+        );
+    }
 }
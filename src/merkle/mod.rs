@@ -1,8 +1,10 @@
 #[cfg(feature = "groth16")]
 pub mod groth16;
 
-use bazuka::zk::ZkScalar;
+use bazuka::zk::{PoseidonHasher, ZkDataLocator, ZkHasher, ZkScalar, ZkStateBuilder, ZkStateModel};
 use ff::Field;
+use std::collections::HashMap;
+use std::marker::PhantomData;
 
 #[derive(Debug, Clone)]
 pub struct Proof<const LOG4_TREE_SIZE: u8>(pub Vec<[ZkScalar; 3]>);
@@ -12,3 +14,321 @@ impl<const LOG4_TREE_SIZE: u8> Default for Proof<LOG4_TREE_SIZE> {
         Self(vec![[ZkScalar::zero(); 3]; LOG4_TREE_SIZE as usize])
     }
 }
+
+// Output of ZkStateBuilder::prove, gives the vector back when its depth is not LOG4_TREE_SIZE
+impl<const LOG4_TREE_SIZE: u8> TryFrom<Vec<[ZkScalar; 3]>> for Proof<LOG4_TREE_SIZE> {
+    type Error = Vec<[ZkScalar; 3]>;
+    fn try_from(proof: Vec<[ZkScalar; 3]>) -> Result<Self, Self::Error> {
+        if proof.len() == LOG4_TREE_SIZE as usize {
+            Ok(Self(proof))
+        } else {
+            Err(proof)
+        }
+    }
+}
+
+// Witness that the item at key of the list at locator still has item_type's default
+// value, None if the item is set
+pub fn non_membership_proof<H: ZkHasher, const LOG4_TREE_SIZE: u8>(
+    builder: &ZkStateBuilder<H>,
+    locator: ZkDataLocator,
+    item_type: &ZkStateModel,
+    key: u32,
+) -> Option<Proof<LOG4_TREE_SIZE>> {
+    let item = builder.get(locator.index(key)).ok()?;
+    if item != item_type.compress_default::<H>() {
+        return None;
+    }
+    Proof::try_from(builder.prove(locator, key).ok()?).ok()
+}
+
+// Hash val up the given levels of siblings, val being at index of the lowest level
+fn climb<H: ZkHasher>(mut index: u64, val: ZkScalar, siblings: &[[ZkScalar; 3]]) -> ZkScalar {
+    siblings.iter().fold(val, |curr, level| {
+        let mut children = level.to_vec();
+        children.insert((index % 4) as usize, curr);
+        index /= 4;
+        H::hash(&children)
+    })
+}
+
+// Proof of a batch of leaves of the same tree, whose indices don't need to be known
+// when building the circuit (Only their count). The nodes at depth TOP_LEVELS are
+// shared by all the leaves: they are included once, and each leaf only comes with
+// its siblings below them
+#[derive(Debug, Clone)]
+pub struct MultiProof<const LOG4_TREE_SIZE: u8, const TOP_LEVELS: u8> {
+    // Per leaf, the siblings of the LOG4_TREE_SIZE - TOP_LEVELS lowest levels
+    pub paths: Vec<Vec<[ZkScalar; 3]>>,
+    // The 4^TOP_LEVELS nodes at depth TOP_LEVELS, from left to right
+    pub top: Vec<ZkScalar>,
+}
+
+impl<const LOG4_TREE_SIZE: u8, const TOP_LEVELS: u8> MultiProof<LOG4_TREE_SIZE, TOP_LEVELS> {
+    pub fn lower_levels() -> usize {
+        assert!(TOP_LEVELS <= LOG4_TREE_SIZE && LOG4_TREE_SIZE < 16);
+        (LOG4_TREE_SIZE - TOP_LEVELS) as usize
+    }
+
+    pub fn num_top_nodes() -> usize {
+        1 << (2 * TOP_LEVELS as usize)
+    }
+
+    // Build from the list at locator, None if an index is out of the tree
+    pub fn build<H: ZkHasher>(
+        builder: &ZkStateBuilder<H>,
+        locator: ZkDataLocator,
+        indices: &[u32],
+    ) -> Option<Self> {
+        let lower = Self::lower_levels();
+        let lower_path = |index: u32| -> Option<Vec<[ZkScalar; 3]>> {
+            if index as u64 >= 1 << (2 * LOG4_TREE_SIZE as u64) {
+                return None;
+            }
+            let mut proof = builder.prove(locator.clone(), index).ok()?;
+            if proof.len() != LOG4_TREE_SIZE as usize {
+                return None;
+            }
+            proof.truncate(lower);
+            Some(proof)
+        };
+        let paths = indices
+            .iter()
+            .map(|index| lower_path(*index))
+            .collect::<Option<Vec<_>>>()?;
+        // A top node is reached from the first leaf below it
+        let top = (0..Self::num_top_nodes() as u32)
+            .map(|node| {
+                let index = node << (2 * lower);
+                let val = builder.get(locator.index(index)).ok()?;
+                Some(climb::<H>(index as u64, val, &lower_path(index)?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { paths, top })
+    }
+
+    // Root of the tree, given the indices of the leaves and their values, None if
+    // a leaf doesn't match the top nodes
+    pub fn root<H: ZkHasher>(&self, indices: &[u32], vals: &[ZkScalar]) -> Option<ZkScalar> {
+        let lower = Self::lower_levels();
+        if indices.len() != self.paths.len()
+            || vals.len() != self.paths.len()
+            || self.top.len() != Self::num_top_nodes()
+        {
+            return None;
+        }
+        for ((index, val), path) in indices.iter().zip(vals.iter()).zip(self.paths.iter()) {
+            let top_index = (*index as u64) >> (2 * lower);
+            if path.len() != lower
+                || top_index >= self.top.len() as u64
+                || climb::<H>(*index as u64, *val, path) != self.top[top_index as usize]
+            {
+                return None;
+            }
+        }
+        let mut layer = self.top.clone();
+        while layer.len() > 1 {
+            layer = layer.chunks(4).map(H::hash).collect();
+        }
+        Some(layer[0])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BinaryProof<const DEPTH: u8>(pub Vec<ZkScalar>);
+
+impl<const DEPTH: u8> Default for BinaryProof<DEPTH> {
+    fn default() -> Self {
+        Self(vec![ZkScalar::zero(); DEPTH as usize])
+    }
+}
+
+impl<const DEPTH: u8> BinaryProof<DEPTH> {
+    // Root of the tree which has val at index, H hashes the two children of a node
+    pub fn root<H: ZkHasher>(&self, index: u64, val: ZkScalar) -> ZkScalar {
+        self.0
+            .iter()
+            .enumerate()
+            .fold(val, |curr, (level, sibling)| {
+                if index >> level & 1 == 1 {
+                    H::hash(&[*sibling, curr])
+                } else {
+                    H::hash(&[curr, *sibling])
+                }
+            })
+    }
+}
+
+// Sparse binary tree of 2^DEPTH leaves, H hashes the two children of a node (e.g.
+// PoseidonArity2Hasher). Unset leaves are default_leaf, zero unless built with
+// with_default_leaf, and the empty subtrees are hashes of it
+#[derive(Debug, Clone)]
+pub struct BinaryTree<H: ZkHasher, const DEPTH: u8> {
+    // Non-default nodes, from the leaves up to the root
+    levels: Vec<HashMap<u64, ZkScalar>>,
+    // Value of an empty subtree at each level
+    defaults: Vec<ZkScalar>,
+    _hasher: PhantomData<H>,
+}
+
+impl<H: ZkHasher, const DEPTH: u8> Default for BinaryTree<H, DEPTH> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: ZkHasher, const DEPTH: u8> BinaryTree<H, DEPTH> {
+    pub fn new() -> Self {
+        Self::with_default_leaf(ZkScalar::zero())
+    }
+
+    pub fn with_default_leaf(default_leaf: ZkScalar) -> Self {
+        assert!(DEPTH < 64);
+        let mut defaults = vec![default_leaf];
+        for level in 0..DEPTH as usize {
+            defaults.push(H::hash(&[defaults[level], defaults[level]]));
+        }
+        Self {
+            levels: vec![HashMap::new(); DEPTH as usize + 1],
+            defaults,
+            _hasher: PhantomData,
+        }
+    }
+
+    fn node(&self, level: usize, index: u64) -> ZkScalar {
+        self.levels[level]
+            .get(&index)
+            .cloned()
+            .unwrap_or(self.defaults[level])
+    }
+
+    pub fn get(&self, index: u64) -> ZkScalar {
+        self.node(0, index)
+    }
+
+    pub fn root(&self) -> ZkScalar {
+        self.node(DEPTH as usize, 0)
+    }
+
+    pub fn set(&mut self, mut index: u64, val: ZkScalar) {
+        assert!(index < 1 << DEPTH);
+        self.levels[0].insert(index, val);
+        for level in 0..DEPTH as usize {
+            let (left, right) = (self.node(level, index & !1), self.node(level, index | 1));
+            index >>= 1;
+            self.levels[level + 1].insert(index, H::hash(&[left, right]));
+        }
+    }
+
+    pub fn prove(&self, mut index: u64) -> BinaryProof<DEPTH> {
+        assert!(index < 1 << DEPTH);
+        let mut proof = Vec::new();
+        for level in 0..DEPTH as usize {
+            proof.push(self.node(level, index ^ 1));
+            index >>= 1;
+        }
+        BinaryProof(proof)
+    }
+}
+
+// Append-only 4-ary tree, same shape as ZkStateBuilder<PoseidonHasher> over a list of
+// scalars, keeping only the frontier (The filled children of the rightmost open node of
+// each level) so that memory is O(LOG4_TREE_SIZE)
+#[derive(Debug, Clone)]
+pub struct IncrementalTree<const LOG4_TREE_SIZE: u8> {
+    size: u64,
+    // Has LOG4_TREE_SIZE + 1 levels, the last one only holds the root once the tree is full
+    frontier: Vec<Vec<ZkScalar>>,
+    // Value of an empty subtree at each level
+    defaults: Vec<ZkScalar>,
+}
+
+impl<const LOG4_TREE_SIZE: u8> Default for IncrementalTree<LOG4_TREE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const LOG4_TREE_SIZE: u8> IncrementalTree<LOG4_TREE_SIZE> {
+    pub fn new() -> Self {
+        assert!(LOG4_TREE_SIZE < 32);
+        let mut defaults = vec![ZkScalar::zero()];
+        for level in 0..LOG4_TREE_SIZE as usize {
+            defaults.push(PoseidonHasher::hash(&[defaults[level]; 4]));
+        }
+        Self {
+            size: 0,
+            frontier: vec![Vec::new(); LOG4_TREE_SIZE as usize + 1],
+            defaults,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn capacity(&self) -> u64 {
+        1 << (2 * LOG4_TREE_SIZE as u64)
+    }
+
+    // Commitment to the root and the size, the state checked by append_leaves
+    pub fn state(&self) -> ZkScalar {
+        PoseidonHasher::hash(&[self.root(), ZkScalar::from(self.size)])
+    }
+
+    pub fn root(&self) -> ZkScalar {
+        if let Some(root) = self.frontier[LOG4_TREE_SIZE as usize].first() {
+            return *root;
+        }
+        let mut carry = None;
+        for level in 0..LOG4_TREE_SIZE as usize {
+            if self.frontier[level].is_empty() && carry.is_none() {
+                continue;
+            }
+            let mut children = self.frontier[level].clone();
+            children.extend(carry);
+            children.resize(4, self.defaults[level]);
+            carry = Some(PoseidonHasher::hash(&children));
+        }
+        carry.unwrap_or(self.defaults[LOG4_TREE_SIZE as usize])
+    }
+
+    // Merkle proof of the next empty leaf, None if the tree is full. Siblings on the left
+    // of the path are in the frontier and the ones on the right are empty
+    pub fn next_proof(&self) -> Option<Proof<LOG4_TREE_SIZE>> {
+        if self.size == self.capacity() {
+            return None;
+        }
+        Some(Proof(
+            self.frontier[..LOG4_TREE_SIZE as usize]
+                .iter()
+                .zip(self.defaults.iter())
+                .map(|(filled, default)| {
+                    let mut siblings = [*default; 3];
+                    siblings[..filled.len()].copy_from_slice(filled);
+                    siblings
+                })
+                .collect(),
+        ))
+    }
+
+    // Append leaf, returning its index, None if the tree is full
+    pub fn append(&mut self, leaf: ZkScalar) -> Option<u64> {
+        if self.size == self.capacity() {
+            return None;
+        }
+        let mut node = leaf;
+        let mut level = 0;
+        loop {
+            self.frontier[level].push(node);
+            if level == LOG4_TREE_SIZE as usize || self.frontier[level].len() < 4 {
+                break;
+            }
+            node = PoseidonHasher::hash(&self.frontier[level]);
+            self.frontier[level].clear();
+            level += 1;
+        }
+        self.size += 1;
+        Some(self.size - 1)
+    }
+}
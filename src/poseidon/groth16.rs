@@ -1,9 +1,13 @@
+use super::{
+    PoseidonArity2Hasher, PoseidonParams, POSEIDON_ARITY2, POSEIDON_ARITY3, POSEIDON_ARITY4,
+};
 use crate::common::groth16::WrappedLc;
 use crate::BellmanFr;
 
-use bazuka::zk::poseidon4::{MDS_MATRIX, ROUNDSF, ROUNDSP, ROUND_CONSTANTS};
+use bazuka::zk::{PoseidonHasher, ZkHasher, ZkScalar};
 use bellman::gadgets::num::AllocatedNum;
 use bellman::{ConstraintSystem, LinearCombination, SynthesisError};
+use ff::Field;
 
 pub fn compress<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
@@ -23,25 +27,25 @@ pub fn sbox<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
     a: WrappedLc,
 ) -> Result<WrappedLc, SynthesisError> {
-    let a2 = AllocatedNum::alloc(&mut *cs, || {
+    let a2 = AllocatedNum::alloc(cs.namespace(|| "a2"), || {
         a.1.map(|v| v.square())
             .ok_or(SynthesisError::AssignmentMissing)
     })?;
     cs.enforce(
-        || "",
+        || "a * a == a2",
         |lc| lc + &a.0,
         |lc| lc + &a.0,
         |lc| lc + a2.get_variable(),
     );
-    let a4 = a2.mul(&mut *cs, &a2)?;
-    let a5 = AllocatedNum::alloc(&mut *cs, || {
+    let a4 = a2.mul(cs.namespace(|| "a4"), &a2)?;
+    let a5 = AllocatedNum::alloc(cs.namespace(|| "a5"), || {
         a4.get_value()
             .zip(a.1)
             .map(|(a4, a)| a4 * a)
             .ok_or(SynthesisError::AssignmentMissing)
     })?;
     cs.enforce(
-        || "",
+        || "a4 * a == a5",
         |lc| lc + a4.get_variable(),
         |lc| lc + &a.0,
         |lc| lc + a5.get_variable(),
@@ -53,59 +57,244 @@ pub fn sbox<CS: ConstraintSystem<BellmanFr>>(
 }
 
 pub fn add_constants<CS: ConstraintSystem<BellmanFr>>(
-    vals: &mut [WrappedLc; 5],
+    params: &PoseidonParams,
+    vals: &mut [WrappedLc],
     const_offset: usize,
 ) {
-    for i in 0..5 {
-        vals[i].add_constant::<CS>(ROUND_CONSTANTS[const_offset + i].into());
+    for (i, val) in vals.iter_mut().enumerate() {
+        val.add_constant::<CS>(params.round_constants[const_offset + i].into());
     }
 }
 
 pub fn partial_round<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
+    params: &PoseidonParams,
     const_offset: usize,
-    mut vals: [WrappedLc; 5],
-) -> Result<[WrappedLc; 5], SynthesisError> {
-    add_constants::<CS>(&mut vals, const_offset);
+    mut vals: Vec<WrappedLc>,
+) -> Result<Vec<WrappedLc>, SynthesisError> {
+    add_constants::<CS>(params, &mut vals, const_offset);
 
-    vals[0] = sbox(&mut *cs, vals[0].clone())?;
-    for i in 1..5 {
-        vals[i] = WrappedLc::alloc_num(compress(&mut *cs, vals[i].clone())?);
+    vals[0] = sbox(&mut cs.namespace(|| "sbox 0"), vals[0].clone())?;
+    for (i, val) in vals.iter_mut().enumerate().skip(1) {
+        *val = WrappedLc::alloc_num(compress(
+            &mut cs.namespace(|| format!("compress {}", i)),
+            val.clone(),
+        )?);
     }
 
-    product_mds(vals)
+    product_mds(params, vals)
 }
 
 pub fn full_round<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
+    params: &PoseidonParams,
     const_offset: usize,
-    mut vals: [WrappedLc; 5],
-) -> Result<[WrappedLc; 5], SynthesisError> {
-    add_constants::<CS>(&mut vals, const_offset);
+    mut vals: Vec<WrappedLc>,
+) -> Result<Vec<WrappedLc>, SynthesisError> {
+    add_constants::<CS>(params, &mut vals, const_offset);
 
-    for i in 0..5 {
-        vals[i] = sbox(&mut *cs, vals[i].clone())?;
+    for (i, val) in vals.iter_mut().enumerate() {
+        *val = sbox(&mut cs.namespace(|| format!("sbox {}", i)), val.clone())?;
     }
 
-    product_mds(vals)
+    product_mds(params, vals)
+}
+
+pub fn product_mds(
+    params: &PoseidonParams,
+    vals: Vec<WrappedLc>,
+) -> Result<Vec<WrappedLc>, SynthesisError> {
+    Ok(product_matrix(&params.mds_matrix, &vals))
+}
+
+pub(crate) fn product_matrix(matrix: &[Vec<ZkScalar>], vals: &[WrappedLc]) -> Vec<WrappedLc> {
+    let mut result = vec![WrappedLc::zero(); vals.len()];
+    for (res, row) in result.iter_mut().zip(matrix.iter()) {
+        for (val, mat_val) in vals.iter().zip(row.iter()) {
+            let mat_val: BellmanFr = (*mat_val).into();
+            *res = res.clone() + val.scale(mat_val);
+        }
+    }
+    result
 }
 
-pub fn product_mds(vals: [WrappedLc; 5]) -> Result<[WrappedLc; 5], SynthesisError> {
-    let mut result = [
-        WrappedLc::zero(),
-        WrappedLc::zero(),
-        WrappedLc::zero(),
-        WrappedLc::zero(),
-        WrappedLc::zero(),
-    ];
-    for j in 0..5 {
-        for k in 0..5 {
-            let mat_val: BellmanFr = MDS_MATRIX[j][k].into();
-            result[j].0 = result[j].0.clone() + (mat_val, &vals[k].0);
-            result[j].1 = result[j].1.zip(vals[k].1).map(|(r, v)| r + v * mat_val);
+pub fn permute<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    params: &PoseidonParams,
+    mut elems: Vec<WrappedLc>,
+) -> Result<Vec<WrappedLc>, SynthesisError> {
+    assert_eq!(elems.len(), params.width);
+    let mut const_offset = 0;
+    for round in 0..params.full_rounds + params.partial_rounds {
+        let cs = &mut cs.namespace(|| format!("round {}", round));
+        let half_full = params.full_rounds / 2;
+        elems = if round < half_full || round >= half_full + params.partial_rounds {
+            full_round(cs, params, const_offset, elems)?
+        } else {
+            partial_round(cs, params, const_offset, elems)?
+        };
+        const_offset += params.width;
+    }
+    Ok(elems)
+}
+
+// Lanes kept as coefficient vectors over a shared basis (The constant one, the lanes
+// entering the partial rounds and the sbox outputs), so that linear combinations
+// don't need compressing and don't grow with repeated MDS products
+pub(crate) struct SymbolicLanes {
+    basis: Vec<WrappedLc>,
+    lanes: Vec<Vec<BellmanFr>>,
+}
+
+impl SymbolicLanes {
+    pub(crate) fn new<CS: ConstraintSystem<BellmanFr>>(vals: Vec<WrappedLc>) -> Self {
+        let width = vals.len();
+        let mut basis = vec![WrappedLc::constant::<CS>(BellmanFr::one())];
+        basis.extend(vals);
+        let lanes = (0..width)
+            .map(|i| {
+                let mut coeffs = vec![BellmanFr::zero(); width + 1];
+                coeffs[i + 1] = BellmanFr::one();
+                coeffs
+            })
+            .collect();
+        Self { basis, lanes }
+    }
+
+    fn lane(&self, i: usize) -> WrappedLc {
+        let mut result = WrappedLc::zero();
+        for (coeff, val) in self.lanes[i].iter().zip(self.basis.iter()) {
+            if !bool::from(coeff.is_zero()) {
+                result = result + val.scale(*coeff);
+            }
+        }
+        result
+    }
+
+    pub(crate) fn into_lanes(self) -> Vec<WrappedLc> {
+        (0..self.lanes.len()).map(|i| self.lane(i)).collect()
+    }
+
+    // Add constants to the first lanes, sbox the first lane and multiply by matrix
+    // 3 constraints
+    pub(crate) fn partial_round<CS: ConstraintSystem<BellmanFr>>(
+        &mut self,
+        cs: &mut CS,
+        constants: &[ZkScalar],
+        matrix: &[Vec<ZkScalar>],
+    ) -> Result<(), SynthesisError> {
+        for (lane, constant) in self.lanes.iter_mut().zip(constants.iter()) {
+            let constant: BellmanFr = (*constant).into();
+            lane[0] += constant;
+        }
+
+        let sboxed = sbox(&mut cs.namespace(|| "sbox 0"), self.lane(0))?;
+        self.basis.push(sboxed);
+        for lane in self.lanes.iter_mut() {
+            lane.push(BellmanFr::zero());
         }
+        let last = self.basis.len() - 1;
+        self.lanes[0] = vec![BellmanFr::zero(); self.basis.len()];
+        self.lanes[0][last] = BellmanFr::one();
+
+        self.lanes = matrix
+            .iter()
+            .map(|row| {
+                let mut coeffs = vec![BellmanFr::zero(); self.basis.len()];
+                for (mat_val, lane) in row.iter().zip(self.lanes.iter()) {
+                    let mat_val: BellmanFr = (*mat_val).into();
+                    for (c, l) in coeffs.iter_mut().zip(lane.iter()) {
+                        *c += mat_val * l;
+                    }
+                }
+                coeffs
+            })
+            .collect();
+        Ok(())
     }
-    Ok(result)
+}
+
+// Same permutation as permute, without compressing the lanes in the partial rounds
+// params.full_rounds * width * 3 + params.partial_rounds * 3 constraints
+pub fn permute_optimized<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    params: &PoseidonParams,
+    mut elems: Vec<WrappedLc>,
+) -> Result<Vec<WrappedLc>, SynthesisError> {
+    assert_eq!(elems.len(), params.width);
+    let half_full = params.full_rounds / 2;
+    let mut const_offset = 0;
+    for round in 0..half_full {
+        elems = full_round(
+            &mut cs.namespace(|| format!("round {}", round)),
+            params,
+            const_offset,
+            elems,
+        )?;
+        const_offset += params.width;
+    }
+    let mut symbolic = SymbolicLanes::new::<CS>(elems);
+    for round in half_full..half_full + params.partial_rounds {
+        symbolic.partial_round(
+            &mut cs.namespace(|| format!("round {}", round)),
+            &params.round_constants[const_offset..const_offset + params.width],
+            &params.mds_matrix,
+        )?;
+        const_offset += params.width;
+    }
+    elems = symbolic.into_lanes();
+    for round in half_full + params.partial_rounds..params.full_rounds + params.partial_rounds {
+        elems = full_round(
+            &mut cs.namespace(|| format!("round {}", round)),
+            params,
+            const_offset,
+            elems,
+        )?;
+        const_offset += params.width;
+    }
+    Ok(elems)
+}
+
+// Hash exactly params.arity() elements, same as PoseidonParams::hash
+pub fn hash<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    params: &PoseidonParams,
+    vals: &[AllocatedNum<BellmanFr>],
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    assert_eq!(vals.len(), params.arity());
+    hash_lcs(
+        cs,
+        params,
+        vals.iter().cloned().map(WrappedLc::alloc_num).collect(),
+    )
+}
+
+pub(crate) fn hash_lcs<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    params: &PoseidonParams,
+    vals: Vec<WrappedLc>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    let mut elems = vec![WrappedLc::zero()];
+    elems.extend(vals);
+    let elems = permute_optimized(&mut *cs, params, elems)?;
+    compress(&mut cs.namespace(|| "out"), elems[1].clone())
+}
+
+pub fn poseidon2<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    hash(cs, &POSEIDON_ARITY2, &[a, b])
+}
+
+pub fn poseidon3<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+    c: AllocatedNum<BellmanFr>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    hash(cs, &POSEIDON_ARITY3, &[a, b, c])
 }
 
 pub fn poseidon4<CS: ConstraintSystem<BellmanFr>>(
@@ -115,90 +304,205 @@ pub fn poseidon4<CS: ConstraintSystem<BellmanFr>>(
     c: AllocatedNum<BellmanFr>,
     d: AllocatedNum<BellmanFr>,
 ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
-    let mut elems = [
-        WrappedLc(
-            LinearCombination::<BellmanFr>::zero(),
-            Some(BellmanFr::zero()),
-        ),
-        WrappedLc(
-            LinearCombination::<BellmanFr>::zero() + a.get_variable(),
-            a.get_value(),
-        ),
-        WrappedLc(
-            LinearCombination::<BellmanFr>::zero() + b.get_variable(),
-            b.get_value(),
-        ),
-        WrappedLc(
-            LinearCombination::<BellmanFr>::zero() + c.get_variable(),
-            c.get_value(),
-        ),
-        WrappedLc(
-            LinearCombination::<BellmanFr>::zero() + d.get_variable(),
-            d.get_value(),
-        ),
-    ];
-    let mut const_offset = 0;
+    hash(cs, &POSEIDON_ARITY4, &[a, b, c, d])
+}
 
-    for _ in 0..ROUNDSF / 2 {
-        elems = full_round(&mut *cs, const_offset, elems)?;
-        const_offset += 5;
+// Chained poseidon4, same as bazuka's native poseidon
+// A single value is returned as is, use PoseidonSponge when that's not desired
+// Panics on empty vals, just like the native version
+pub fn poseidon<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    vals: &[AllocatedNum<BellmanFr>],
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    if vals.len() == 1 {
+        return Ok(vals[0].clone());
     }
+    poseidon_lcs(cs, vals.iter().cloned().map(WrappedLc::alloc_num).collect())
+}
 
-    for _ in 0..ROUNDSP {
-        elems = partial_round(&mut *cs, const_offset, elems)?;
-        const_offset += 5;
+// Same as poseidon, but on linear combinations, which are fed to the first
+// permutation without being compressed (A single value costs a constraint)
+pub(crate) fn poseidon_lcs<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    vals: Vec<WrappedLc>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    assert!(!vals.is_empty());
+    let mut vals = vals.into_iter();
+    let mut first = vals.next().unwrap();
+    let rest = vals.collect::<Vec<_>>();
+    if rest.is_empty() {
+        return compress(cs, first);
+    }
+    let mut out = None;
+    for (i, chunk) in rest.chunks(3).enumerate() {
+        let mut inputs = vec![first];
+        inputs.extend(chunk.iter().cloned());
+        inputs.resize(4, WrappedLc::zero());
+        let hash = hash_lcs(
+            &mut cs.namespace(|| format!("chunk {}", i)),
+            &POSEIDON_ARITY4,
+            inputs,
+        )?;
+        first = WrappedLc::alloc_num(hash.clone());
+        out = Some(hash);
     }
+    Ok(out.unwrap())
+}
+
+// Gadget counterpart of a native ZkHasher, for picking the hash behind the
+// Merkle and reveal gadgets
+pub trait GadgetHasher: ZkHasher {
+    fn hash_gadget<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[AllocatedNum<BellmanFr>],
+    ) -> Result<AllocatedNum<BellmanFr>, SynthesisError>;
 
-    for _ in 0..ROUNDSF / 2 {
-        elems = full_round(&mut *cs, const_offset, elems)?;
-        const_offset += 5;
+    // Same as hash_gadget on linear combinations, compressing them by default
+    fn hash_lcs_gadget<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[WrappedLc],
+    ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+        let nums = vals
+            .iter()
+            .enumerate()
+            .map(|(i, v)| compress(&mut cs.namespace(|| format!("val {}", i)), v.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::hash_gadget(cs, &nums)
+    }
+}
+
+impl GadgetHasher for PoseidonArity2Hasher {
+    fn hash_gadget<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[AllocatedNum<BellmanFr>],
+    ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+        hash(cs, &POSEIDON_ARITY2, vals)
     }
 
-    compress(cs, elems[1].clone())
+    fn hash_lcs_gadget<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[WrappedLc],
+    ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+        assert_eq!(vals.len(), 2);
+        hash_lcs(cs, &POSEIDON_ARITY2, vals.to_vec())
+    }
 }
 
-pub fn poseidon<CS: ConstraintSystem<BellmanFr>>(
-    cs: &mut CS,
-    vals: &[AllocatedNum<BellmanFr>],
-) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
-    let mut first = vals[0].clone();
+impl GadgetHasher for PoseidonHasher {
+    fn hash_gadget<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[AllocatedNum<BellmanFr>],
+    ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+        poseidon(cs, vals)
+    }
 
-    let zero = AllocatedNum::<BellmanFr>::alloc(&mut *cs, || Ok(BellmanFr::zero()))?;
-    cs.enforce(
-        || "",
-        |lc| lc + zero.get_variable(),
-        |lc| lc + CS::one(),
-        |lc| lc,
-    );
+    fn hash_lcs_gadget<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[WrappedLc],
+    ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+        poseidon_lcs(cs, vals.to_vec())
+    }
+}
 
-    for chunk in vals[1..].chunks(3) {
-        first = match chunk.len() {
-            1 => poseidon4(
-                &mut *cs,
-                first.clone(),
-                chunk[0].clone(),
-                zero.clone(),
-                zero.clone(),
-            )?,
-            2 => poseidon4(
-                &mut *cs,
-                first.clone(),
-                chunk[0].clone(),
-                chunk[1].clone(),
-                zero.clone(),
-            )?,
-            3 => poseidon4(
-                &mut *cs,
-                first.clone(),
-                chunk[0].clone(),
-                chunk[1].clone(),
-                chunk[2].clone(),
-            )?,
-            _ => panic!(),
-        };
+// Gadget version of PoseidonSponge
+pub struct PoseidonSponge<'a> {
+    params: &'a PoseidonParams,
+    initial_state: Option<Vec<ZkScalar>>,
+    state: Vec<WrappedLc>,
+    pos: usize,
+    squeezing: bool,
+    num_permutations: usize,
+    num_outputs: usize,
+}
+
+impl<'a> PoseidonSponge<'a> {
+    pub fn new(params: &'a PoseidonParams, domain: ZkScalar) -> Self {
+        Self {
+            params,
+            initial_state: Some(super::PoseidonSponge::initial_state(params, domain)),
+            state: vec![WrappedLc::zero(); params.width],
+            pos: 0,
+            squeezing: false,
+            num_permutations: 0,
+            num_outputs: 0,
+        }
+    }
+
+    fn permute<CS: ConstraintSystem<BellmanFr>>(
+        &mut self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
+        // The constant initial state only needs to be added before the first permutation
+        if let Some(initial_state) = self.initial_state.take() {
+            for (s, v) in self.state.iter_mut().zip(initial_state) {
+                s.add_constant::<CS>(v.into());
+            }
+        }
+        self.state = permute_optimized(
+            &mut cs.namespace(|| format!("permutation {}", self.num_permutations)),
+            self.params,
+            self.state.clone(),
+        )?;
+        self.num_permutations += 1;
+        self.pos = 0;
+        Ok(())
+    }
+
+    // Costs a permutation every params.arity() values
+    pub fn absorb<CS: ConstraintSystem<BellmanFr>>(
+        &mut self,
+        cs: &mut CS,
+        vals: &[AllocatedNum<BellmanFr>],
+    ) -> Result<(), SynthesisError> {
+        if self.squeezing {
+            self.squeezing = false;
+            self.pos = 0;
+        }
+        for val in vals {
+            if self.pos == self.params.arity() {
+                self.permute(&mut *cs)?;
+            }
+            self.state[1 + self.pos] =
+                self.state[1 + self.pos].clone() + WrappedLc::alloc_num(val.clone());
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    // 1 constraint, plus a permutation every params.arity() outputs
+    pub fn squeeze<CS: ConstraintSystem<BellmanFr>>(
+        &mut self,
+        cs: &mut CS,
+    ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+        let out = self.squeeze_lc(&mut *cs)?;
+        compress(
+            &mut cs.namespace(|| format!("output {}", self.num_outputs - 1)),
+            out,
+        )
     }
 
-    Ok(first)
+    // Same as squeeze without compressing the output, a permutation every
+    // params.arity() outputs
+    pub fn squeeze_lc<CS: ConstraintSystem<BellmanFr>>(
+        &mut self,
+        cs: &mut CS,
+    ) -> Result<WrappedLc, SynthesisError> {
+        if !self.squeezing {
+            if self.pos == self.params.arity() {
+                self.state[0].add_constant::<CS>(BellmanFr::one());
+            } else {
+                self.state[1 + self.pos].add_constant::<CS>(BellmanFr::one());
+            }
+            self.permute(&mut *cs)?;
+            self.squeezing = true;
+        }
+        if self.pos == self.params.arity() {
+            self.permute(&mut *cs)?;
+        }
+        self.pos += 1;
+        self.num_outputs += 1;
+        Ok(self.state[self.pos].clone())
+    }
 }
 
 #[cfg(test)]
@@ -207,9 +511,27 @@ mod test {
     use crate::Bls12;
     use bazuka::zk::ZkScalar;
     use bellman::gadgets::num::AllocatedNum;
+    use bellman::gadgets::test::TestConstraintSystem;
     use bellman::{groth16, Circuit, ConstraintSystem, SynthesisError};
+    use ff::PrimeField;
     use rand::rngs::OsRng;
 
+    #[test]
+    fn test_reference_vectors() {
+        // poseidonperm_x5_255_3 of the Poseidon reference implementation
+        let mut state = [0, 1, 2].map(ZkScalar::from);
+        crate::poseidon::POSEIDON_ARITY2.permute(&mut state);
+        assert_eq!(
+            state,
+            [
+                "18456658763349757341014058622209659766100673761449600566550821987295786346378",
+                "37068251774887509885063625701815026138353041152735229476479055620962268601796",
+                "26763157702141528937904191329664859174584798817251788852101947537759678822298",
+            ]
+            .map(|v| ZkScalar::from_str_vartime(v).unwrap())
+        );
+    }
+
     struct TestPoseidon4Circuit {
         pub a: Option<BellmanFr>,
         pub b: Option<BellmanFr>,
@@ -289,4 +611,232 @@ mod test {
         let proof = groth16::create_random_proof(c, &params, &mut OsRng).unwrap();
         assert!(!groth16::verify_proof(&pvk, &proof, &[expected.into()]).is_ok());
     }
+
+    #[test]
+    fn test_poseidon4_params_match_bazuka() {
+        let vals = [
+            ZkScalar::from(123),
+            ZkScalar::from(234),
+            ZkScalar::from(345),
+            ZkScalar::from(456),
+        ];
+        assert_eq!(
+            crate::poseidon::poseidon4(vals[0], vals[1], vals[2], vals[3]),
+            bazuka::zk::poseidon4::poseidon4(vals[0], vals[1], vals[2], vals[3])
+        );
+    }
+
+    #[test]
+    fn test_generated_params_are_deterministic() {
+        let params = PoseidonParams::generate(3, 8, 57);
+        assert_eq!(
+            params.round_constants,
+            POSEIDON_ARITY2.round_constants.clone()
+        );
+        assert_eq!(params.round_constants.len(), 3 * 65);
+        assert_ne!(
+            POSEIDON_ARITY2.round_constants[..3],
+            POSEIDON_ARITY3.round_constants[..3]
+        );
+    }
+
+    #[test]
+    fn test_poseidon_arities() {
+        // (params, constraints per hash)
+        for (params, num_constraints) in [
+            (&*POSEIDON_ARITY2, 8 * 3 * 3 + 57 * 3 + 1),
+            (&*POSEIDON_ARITY3, 8 * 4 * 3 + 56 * 3 + 1),
+            (&*POSEIDON_ARITY4, 8 * 5 * 3 + 60 * 3 + 1),
+        ] {
+            let vals = (0..params.arity())
+                .map(|i| ZkScalar::from(i as u64 * 111 + 123))
+                .collect::<Vec<_>>();
+            let expected = params.hash(&vals);
+
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let nums = vals
+                .iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    AllocatedNum::alloc(cs.namespace(|| format!("val {}", i)), || Ok((*v).into()))
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            let out = hash(&mut cs.namespace(|| "hash"), params, &nums).unwrap();
+            assert_eq!(out.get_value(), Some(expected.into()));
+            assert!(cs.is_satisfied());
+            assert_eq!(cs.num_constraints(), num_constraints);
+
+            cs.set("val 0/num", BellmanFr::from(124));
+            assert!(!cs.is_satisfied());
+        }
+
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(BellmanFr::from(1))).unwrap();
+        let b = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(BellmanFr::from(2))).unwrap();
+        let c = AllocatedNum::alloc(cs.namespace(|| "c"), || Ok(BellmanFr::from(3))).unwrap();
+        let out2 = poseidon2(&mut cs.namespace(|| "poseidon2"), a.clone(), b.clone()).unwrap();
+        let out3 = poseidon3(&mut cs.namespace(|| "poseidon3"), a, b, c).unwrap();
+        assert_eq!(
+            out2.get_value(),
+            Some(crate::poseidon::poseidon2(ZkScalar::from(1), ZkScalar::from(2)).into())
+        );
+        assert_eq!(
+            out3.get_value(),
+            Some(
+                crate::poseidon::poseidon3(ZkScalar::from(1), ZkScalar::from(2), ZkScalar::from(3))
+                    .into()
+            )
+        );
+        assert!(cs.is_satisfied());
+    }
+
+    fn alloc_vals<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[ZkScalar],
+    ) -> Vec<AllocatedNum<BellmanFr>> {
+        vals.iter()
+            .enumerate()
+            .map(|(i, v)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("val {}", i)), || Ok((*v).into()))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_poseidon_sponge() {
+        let domain = ZkScalar::from(0xd0);
+        for params in [&*POSEIDON_ARITY2, &*POSEIDON_ARITY4] {
+            for (absorbs, squeezes) in [
+                (vec![0], 1),
+                (vec![1], 1),
+                (vec![params.arity()], 3),
+                (vec![params.arity() + 1], 2),
+                (vec![3, 0, 2], 2),
+                (vec![2, 5], 1),
+            ] {
+                let mut native = crate::poseidon::PoseidonSponge::new(params, domain);
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let mut sponge = PoseidonSponge::new(params, domain);
+                let mut next = 0;
+                for (i, num_vals) in absorbs.iter().enumerate() {
+                    let cs = &mut cs.namespace(|| format!("phase {}", i));
+                    let vals = (next..next + *num_vals)
+                        .map(|v| ZkScalar::from(v as u64 + 10))
+                        .collect::<Vec<_>>();
+                    next += num_vals;
+                    native.absorb(&vals);
+                    let nums = alloc_vals(&mut cs.namespace(|| "vals"), &vals);
+                    sponge.absorb(&mut *cs, &nums).unwrap();
+                    for _ in 0..squeezes {
+                        let expected = native.squeeze();
+                        let out = sponge.squeeze(&mut *cs).unwrap();
+                        assert_eq!(out.get_value(), Some(expected.into()));
+                    }
+                }
+                assert!(cs.is_satisfied());
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon_sponge_cost() {
+        let params = &*POSEIDON_ARITY4;
+        let mut counts = Vec::new();
+        for num_vals in [1, params.arity()] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let vals = (0..num_vals as u64).map(ZkScalar::from).collect::<Vec<_>>();
+            let nums = alloc_vals(&mut cs.namespace(|| "vals"), &vals);
+            let before = cs.num_constraints();
+            let mut sponge = PoseidonSponge::new(params, ZkScalar::from(1));
+            sponge.absorb(&mut cs, &nums).unwrap();
+            sponge.squeeze(&mut cs).unwrap();
+            // A full rate is padded without an extra permutation
+            assert_eq!(sponge.num_permutations, 1);
+            counts.push(cs.num_constraints() - before);
+        }
+        assert_eq!(counts[0], counts[1]);
+    }
+
+    #[test]
+    fn test_poseidon_sponge_domain_separation() {
+        let digest = |domain: u64, vals: &[u64]| {
+            let mut sponge =
+                crate::poseidon::PoseidonSponge::new(&POSEIDON_ARITY4, ZkScalar::from(domain));
+            sponge.absorb(&vals.iter().map(|v| ZkScalar::from(*v)).collect::<Vec<_>>());
+            sponge.squeeze()
+        };
+        assert_ne!(digest(1, &[5]), digest(2, &[5]));
+        // The pad of a full rate can't move a message to the next domain
+        assert_ne!(digest(1, &[5, 6, 7, 1]), digest(2, &[5, 6, 7]));
+        assert_ne!(digest(0, &[1, 1, 1, 1]), digest(1, &[1, 1, 1]));
+        assert_ne!(digest(1, &[5]), digest(1, &[5, 0]));
+        assert_ne!(digest(1, &[]), digest(1, &[0]));
+        assert_ne!(digest(1, &[1, 2, 3, 4]), digest(1, &[1, 2, 3, 4, 0]));
+        assert_eq!(digest(1, &[1, 2, 3]), digest(1, &[1, 2, 3]));
+
+        let mut sponge = crate::poseidon::PoseidonSponge::new(&POSEIDON_ARITY4, ZkScalar::from(1));
+        let outs = (0..6).map(|_| sponge.squeeze()).collect::<Vec<_>>();
+        for i in 0..outs.len() {
+            for j in i + 1..outs.len() {
+                assert_ne!(outs[i], outs[j]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon_empty_and_single() {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        // Empty input panics, both natively and in the gadget
+        assert!(std::panic::catch_unwind(|| PoseidonHasher::hash(&[])).is_err());
+        assert!(std::panic::catch_unwind(|| {
+            poseidon(&mut TestConstraintSystem::<BellmanFr>::new(), &[])
+        })
+        .is_err());
+        let vals = alloc_vals(&mut cs.namespace(|| "vals"), &[ZkScalar::from(7)]);
+        let out = poseidon(&mut cs.namespace(|| "single"), &vals).unwrap();
+        assert_eq!(out.get_value(), Some(BellmanFr::from(7)));
+        assert!(cs.is_satisfied());
+    }
+
+    type PermuteGadget = fn(
+        &mut TestConstraintSystem<BellmanFr>,
+        &PoseidonParams,
+        Vec<WrappedLc>,
+    ) -> Result<Vec<WrappedLc>, SynthesisError>;
+
+    #[test]
+    fn test_permute_optimized() {
+        for params in [&*POSEIDON_ARITY2, &*POSEIDON_ARITY3, &*POSEIDON_ARITY4] {
+            let vals = (0..params.width)
+                .map(|i| ZkScalar::from(i as u64 * 7 + 3))
+                .collect::<Vec<_>>();
+            let mut expected = vals.clone();
+            params.permute(&mut expected);
+
+            let mut counts = Vec::new();
+            let permutations: [PermuteGadget; 2] = [permute, permute_optimized];
+            for permutation in permutations {
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let nums = alloc_vals(&mut cs.namespace(|| "vals"), &vals);
+                let elems = nums.into_iter().map(WrappedLc::alloc_num).collect();
+                let out = permutation(&mut cs, params, elems).unwrap();
+                for (i, (out, expected)) in out.into_iter().zip(expected.iter()).enumerate() {
+                    let out = compress(&mut cs.namespace(|| format!("out {}", i)), out).unwrap();
+                    assert_eq!(out.get_value(), Some((*expected).into()));
+                }
+                assert!(cs.is_satisfied());
+                counts.push(cs.num_constraints());
+
+                cs.set("vals/val 0/num", BellmanFr::from(4));
+                assert!(!cs.is_satisfied());
+            }
+            // The lanes that aren't sboxed are no longer compressed in partial rounds
+            assert_eq!(
+                counts[0] - counts[1],
+                params.partial_rounds * (params.width - 1)
+            );
+        }
+    }
 }
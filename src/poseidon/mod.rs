@@ -1,2 +1,279 @@
 #[cfg(feature = "groth16")]
 pub mod groth16;
+
+use bazuka::zk::{ZkHasher, ZkScalar};
+use ff::{Field, PrimeField};
+
+// Parameters of a Poseidon permutation over ZkScalar with x^5 sbox
+// The first element of the state is the capacity, the rest are inputs
+#[derive(Debug, Clone)]
+pub struct PoseidonParams {
+    pub width: usize,
+    pub full_rounds: usize,
+    pub partial_rounds: usize,
+    pub round_constants: Vec<ZkScalar>,
+    pub mds_matrix: Vec<Vec<ZkScalar>>,
+}
+
+// Grain LFSR used by the Poseidon reference implementation for deriving constants
+pub(crate) struct Grain {
+    state: [bool; 80],
+}
+
+impl Grain {
+    pub(crate) fn new(width: usize, full_rounds: usize, partial_rounds: usize) -> Self {
+        let mut bits = Vec::with_capacity(80);
+        let mut push = |val: usize, len: usize| {
+            for i in (0..len).rev() {
+                bits.push((val >> i) & 1 == 1);
+            }
+        };
+        push(1, 2); // Prime field
+        push(0, 4); // x^alpha sbox
+        push(ZkScalar::NUM_BITS as usize, 12);
+        push(width, 12);
+        push(full_rounds, 10);
+        push(partial_rounds, 10);
+        push((1 << 30) - 1, 30);
+        let mut grain = Self {
+            state: bits.try_into().unwrap(),
+        };
+        for _ in 0..160 {
+            grain.next_bit();
+        }
+        grain
+    }
+    fn next_bit(&mut self) -> bool {
+        let s = &self.state;
+        let bit = s[62] ^ s[51] ^ s[38] ^ s[23] ^ s[13] ^ s[0];
+        self.state.rotate_left(1);
+        self.state[79] = bit;
+        bit
+    }
+    fn next_filtered_bit(&mut self) -> bool {
+        loop {
+            let keep = self.next_bit();
+            let bit = self.next_bit();
+            if keep {
+                return bit;
+            }
+        }
+    }
+    // NUM_BITS bits reduced modulo the field size, as used for the MDS matrix
+    fn next_reduced_scalar(&mut self) -> ZkScalar {
+        (0..ZkScalar::NUM_BITS).fold(ZkScalar::zero(), |acc, _| {
+            if self.next_filtered_bit() {
+                acc.double() + ZkScalar::one()
+            } else {
+                acc.double()
+            }
+        })
+    }
+    pub(crate) fn next_scalar(&mut self) -> ZkScalar {
+        loop {
+            let mut repr = <ZkScalar as PrimeField>::Repr::default();
+            for i in (0..ZkScalar::NUM_BITS as usize).rev() {
+                if self.next_filtered_bit() {
+                    repr.as_mut()[i / 8] |= 1 << (i % 8);
+                }
+            }
+            if let Some(v) = Option::from(ZkScalar::from_repr(repr)) {
+                return v;
+            }
+        }
+    }
+}
+
+impl PoseidonParams {
+    // Round constants and a Cauchy MDS matrix 1 / (x_i + y_j), both drawn from the
+    // Grain LFSR, same as generate_parameters_grain.sage of the reference
+    // implementation (Whose extra checks against invariant subspaces are not run,
+    // a random Cauchy matrix over a 255-bit field passes them with overwhelming
+    // probability)
+    pub fn generate(width: usize, full_rounds: usize, partial_rounds: usize) -> Self {
+        let mut grain = Grain::new(width, full_rounds, partial_rounds);
+        let round_constants = (0..width * (full_rounds + partial_rounds))
+            .map(|_| grain.next_scalar())
+            .collect();
+        let mds_matrix = loop {
+            let elems = (0..2 * width)
+                .map(|_| grain.next_reduced_scalar())
+                .collect::<Vec<_>>();
+            let (xs, ys) = elems.split_at(width);
+            let distinct = elems
+                .iter()
+                .enumerate()
+                .all(|(i, a)| elems[..i].iter().all(|b| a != b));
+            if !distinct {
+                continue;
+            }
+            let matrix = xs
+                .iter()
+                .map(|x| {
+                    ys.iter()
+                        .map(|y| Option::from((*x + y).invert()))
+                        .collect::<Option<Vec<ZkScalar>>>()
+                })
+                .collect::<Option<Vec<_>>>();
+            if let Some(matrix) = matrix {
+                break matrix;
+            }
+        };
+        Self {
+            width,
+            full_rounds,
+            partial_rounds,
+            round_constants,
+            mds_matrix,
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.width - 1
+    }
+
+    pub fn permute(&self, state: &mut [ZkScalar]) {
+        assert_eq!(state.len(), self.width);
+        let half_full = self.full_rounds / 2;
+        for round in 0..self.full_rounds + self.partial_rounds {
+            let constants = &self.round_constants[round * self.width..(round + 1) * self.width];
+            for (s, c) in state.iter_mut().zip(constants.iter()) {
+                *s += c;
+            }
+            if round < half_full || round >= half_full + self.partial_rounds {
+                state.iter_mut().for_each(|s| *s = sbox(*s));
+            } else {
+                state[0] = sbox(state[0]);
+            }
+            let mixed = self
+                .mds_matrix
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .zip(state.iter())
+                        .fold(ZkScalar::zero(), |acc, (m, s)| acc + *m * s)
+                })
+                .collect::<Vec<_>>();
+            state.copy_from_slice(&mixed);
+        }
+    }
+
+    // Hash exactly arity() elements
+    pub fn hash(&self, vals: &[ZkScalar]) -> ZkScalar {
+        assert_eq!(vals.len(), self.arity());
+        let mut state = vec![ZkScalar::zero()];
+        state.extend_from_slice(vals);
+        self.permute(&mut state);
+        state[1]
+    }
+}
+
+pub(crate) fn sbox(v: ZkScalar) -> ZkScalar {
+    v.square().square() * v
+}
+
+lazy_static::lazy_static! {
+    pub static ref POSEIDON_ARITY2: PoseidonParams = PoseidonParams::generate(3, 8, 57);
+    pub static ref POSEIDON_ARITY3: PoseidonParams = PoseidonParams::generate(4, 8, 56);
+    // Same permutation as bazuka::zk::poseidon4
+    pub static ref POSEIDON_ARITY4: PoseidonParams = {
+        use bazuka::zk::poseidon4::{MDS_MATRIX, ROUNDSF, ROUNDSP, ROUND_CONSTANTS};
+        PoseidonParams {
+            width: 5,
+            full_rounds: ROUNDSF,
+            partial_rounds: ROUNDSP,
+            round_constants: ROUND_CONSTANTS.iter().cloned().collect(),
+            mds_matrix: MDS_MATRIX.iter().map(|row| row.to_vec()).collect(),
+        }
+    };
+}
+
+pub fn poseidon2(a: ZkScalar, b: ZkScalar) -> ZkScalar {
+    POSEIDON_ARITY2.hash(&[a, b])
+}
+
+// Two-to-one hasher on POSEIDON_ARITY2, for binary trees. Same as the reference
+// poseidonperm_x5_255_3 permutation on [0, a, b], taking the first rate element
+// vals must have exactly 2 elements
+#[derive(Debug, Clone)]
+pub struct PoseidonArity2Hasher;
+
+impl ZkHasher for PoseidonArity2Hasher {
+    fn hash(vals: &[ZkScalar]) -> ZkScalar {
+        POSEIDON_ARITY2.hash(vals)
+    }
+}
+
+pub fn poseidon3(a: ZkScalar, b: ZkScalar, c: ZkScalar) -> ZkScalar {
+    POSEIDON_ARITY3.hash(&[a, b, c])
+}
+
+pub fn poseidon4(a: ZkScalar, b: ZkScalar, c: ZkScalar, d: ZkScalar) -> ZkScalar {
+    POSEIDON_ARITY4.hash(&[a, b, c, d])
+}
+
+// Duplex sponge over a Poseidon permutation, starting from the permutation of the
+// domain tag in the capacity (Constant, so free in circuits)
+// Inputs are padded with a single one before squeezing, which goes to the capacity
+// when the rate is exactly full, so that no extra permutation is needed. Since the
+// tag has been permuted, that pad can't turn one domain into another (Tags D and
+// D + 1 don't meet)
+#[derive(Debug, Clone)]
+pub struct PoseidonSponge<'a> {
+    params: &'a PoseidonParams,
+    state: Vec<ZkScalar>,
+    pos: usize,
+    squeezing: bool,
+}
+
+impl<'a> PoseidonSponge<'a> {
+    pub fn new(params: &'a PoseidonParams, domain: ZkScalar) -> Self {
+        Self {
+            params,
+            state: Self::initial_state(params, domain),
+            pos: 0,
+            squeezing: false,
+        }
+    }
+
+    pub(crate) fn initial_state(params: &PoseidonParams, domain: ZkScalar) -> Vec<ZkScalar> {
+        let mut state = vec![ZkScalar::zero(); params.width];
+        state[0] = domain;
+        params.permute(&mut state);
+        state
+    }
+
+    pub fn absorb(&mut self, vals: &[ZkScalar]) {
+        if self.squeezing {
+            self.squeezing = false;
+            self.pos = 0;
+        }
+        for val in vals {
+            if self.pos == self.params.arity() {
+                self.params.permute(&mut self.state);
+                self.pos = 0;
+            }
+            self.state[1 + self.pos] += val;
+            self.pos += 1;
+        }
+    }
+
+    pub fn squeeze(&mut self) -> ZkScalar {
+        if !self.squeezing {
+            if self.pos == self.params.arity() {
+                self.state[0] += ZkScalar::one();
+            } else {
+                self.state[1 + self.pos] += ZkScalar::one();
+            }
+            self.params.permute(&mut self.state);
+            self.squeezing = true;
+            self.pos = 0;
+        }
+        if self.pos == self.params.arity() {
+            self.params.permute(&mut self.state);
+            self.pos = 0;
+        }
+        self.pos += 1;
+        self.state[self.pos]
+    }
+}
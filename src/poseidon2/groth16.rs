@@ -0,0 +1,350 @@
+use super::{Poseidon2Hasher, Poseidon2Params, POSEIDON2_WIDTH4};
+use crate::common::groth16::WrappedLc;
+use crate::poseidon::groth16::{compress, product_matrix, sbox, GadgetHasher, SymbolicLanes};
+use crate::BellmanFr;
+
+use bellman::gadgets::num::AllocatedNum;
+use bellman::{ConstraintSystem, SynthesisError};
+
+// width * 3 constraints
+fn external_round<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    params: &Poseidon2Params,
+    round: usize,
+    mut vals: Vec<WrappedLc>,
+) -> Result<Vec<WrappedLc>, SynthesisError> {
+    let constants = &params.external_constants[round * params.width..(round + 1) * params.width];
+    for (i, (val, constant)) in vals.iter_mut().zip(constants.iter()).enumerate() {
+        val.add_constant::<CS>((*constant).into());
+        *val = sbox(&mut cs.namespace(|| format!("sbox {}", i)), val.clone())?;
+    }
+    Ok(product_matrix(&params.external_matrix, &vals))
+}
+
+// params.full_rounds * width * 3 + params.partial_rounds * 3 constraints
+pub fn permute<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    params: &Poseidon2Params,
+    elems: Vec<WrappedLc>,
+) -> Result<Vec<WrappedLc>, SynthesisError> {
+    assert_eq!(elems.len(), params.width);
+    let half_full = params.full_rounds / 2;
+    let mut elems = product_matrix(&params.external_matrix, &elems);
+    for round in 0..half_full {
+        elems = external_round(
+            &mut cs.namespace(|| format!("external round {}", round)),
+            params,
+            round,
+            elems,
+        )?;
+    }
+    let mut symbolic = SymbolicLanes::new::<CS>(elems);
+    for (round, constant) in params.internal_constants.iter().enumerate() {
+        symbolic.partial_round(
+            &mut cs.namespace(|| format!("internal round {}", round)),
+            &[*constant],
+            &params.internal_matrix,
+        )?;
+    }
+    elems = symbolic.into_lanes();
+    for round in half_full..params.full_rounds {
+        elems = external_round(
+            &mut cs.namespace(|| format!("external round {}", round)),
+            params,
+            round,
+            elems,
+        )?;
+    }
+    Ok(elems)
+}
+
+fn hash4_lcs<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    vals: Vec<WrappedLc>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    let first = vals[0].clone();
+    let elems = permute(&mut *cs, &POSEIDON2_WIDTH4, vals)?;
+    compress(&mut cs.namespace(|| "out"), elems[0].clone() + first)
+}
+
+// Same as native hash4, 265 constraints
+pub fn hash4<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: AllocatedNum<BellmanFr>,
+    b: AllocatedNum<BellmanFr>,
+    c: AllocatedNum<BellmanFr>,
+    d: AllocatedNum<BellmanFr>,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    hash4_lcs(
+        cs,
+        [a, b, c, d].into_iter().map(WrappedLc::alloc_num).collect(),
+    )
+}
+
+// Same as native hash, vals must not be empty
+pub fn hash<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    vals: &[AllocatedNum<BellmanFr>],
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    assert!(!vals.is_empty());
+    let mut first = vals[0].clone();
+    for (i, chunk) in vals[1..].chunks(3).enumerate() {
+        let mut inputs = vec![WrappedLc::alloc_num(first)];
+        inputs.extend(chunk.iter().cloned().map(WrappedLc::alloc_num));
+        inputs.resize(4, WrappedLc::zero());
+        first = hash4_lcs(&mut cs.namespace(|| format!("chunk {}", i)), inputs)?;
+    }
+    Ok(first)
+}
+
+impl GadgetHasher for Poseidon2Hasher {
+    fn hash_gadget<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[AllocatedNum<BellmanFr>],
+    ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+        hash(cs, vals)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::poseidon::groth16::poseidon4;
+    use bazuka::zk::ZkScalar;
+    use bellman::gadgets::test::TestConstraintSystem;
+    use ff::{Field, PrimeField};
+
+    fn alloc_vals<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        vals: &[ZkScalar],
+    ) -> Vec<AllocatedNum<BellmanFr>> {
+        vals.iter()
+            .enumerate()
+            .map(|(i, v)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("val {}", i)), || Ok((*v).into()))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_poseidon2_regression_vectors() {
+        // Values of zeekit's own instance (Not reference vectors, there are none for
+        // it), from an independent implementation of generate_width4, so that any
+        // change of the constants or the matrices is caught
+        let mut state = [0, 1, 2, 3].map(ZkScalar::from);
+        POSEIDON2_WIDTH4.permute(&mut state);
+        assert_eq!(
+            state,
+            [
+                "42488569000811756460621184507047739057723123062791927866837077019670565373708",
+                "21480382422527713807161261598313428905288272365636125289747584264262456476640",
+                "29762269021100611393714524293156909839784704917348302193216282352522021487420",
+                "32100336784265849690626029214469217682682085800141013897673639806546387247634",
+            ]
+            .map(|v| ZkScalar::from_str_vartime(v).unwrap())
+        );
+        assert_eq!(
+            super::super::hash4(1.into(), 2.into(), 3.into(), 4.into()),
+            ZkScalar::from_str_vartime(
+                "989212304479123542341039568496424292208144364311672376371016924133035706384"
+            )
+            .unwrap()
+        );
+    }
+
+    type Matrix = Vec<Vec<ZkScalar>>;
+
+    fn mat_mul(a: &Matrix, b: &Matrix) -> Matrix {
+        (0..a.len())
+            .map(|i| {
+                (0..b[0].len())
+                    .map(|j| (0..b.len()).fold(ZkScalar::zero(), |acc, k| acc + a[i][k] * b[k][j]))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Characteristic polynomial (Little-endian coefficients), Faddeev-LeVerrier
+    fn char_poly(m: &Matrix) -> Vec<ZkScalar> {
+        let n = m.len();
+        let mut coeffs = vec![ZkScalar::zero(); n + 1];
+        coeffs[n] = ZkScalar::one();
+        let mut acc = vec![vec![ZkScalar::zero(); n]; n];
+        for k in 1..=n {
+            acc = mat_mul(m, &acc);
+            for (i, row) in acc.iter_mut().enumerate() {
+                row[i] += coeffs[n - k + 1];
+            }
+            let prod = mat_mul(m, &acc);
+            let trace = (0..n).fold(ZkScalar::zero(), |t, i| t + prod[i][i]);
+            coeffs[n - k] = -trace * ZkScalar::from(k as u64).invert().unwrap();
+        }
+        coeffs
+    }
+
+    fn poly_rem(a: &[ZkScalar], f: &[ZkScalar]) -> Vec<ZkScalar> {
+        let mut a = a.to_vec();
+        let deg = f.len() - 1;
+        let lead_inv = f[deg].invert().unwrap();
+        while a.len() > deg {
+            let c = a.pop().unwrap() * lead_inv;
+            let shift = a.len() - deg;
+            for (i, f) in f[..deg].iter().enumerate() {
+                a[shift + i] -= c * f;
+            }
+        }
+        while a.last() == Some(&ZkScalar::zero()) {
+            a.pop();
+        }
+        a
+    }
+
+    fn poly_mul_rem(a: &[ZkScalar], b: &[ZkScalar], f: &[ZkScalar]) -> Vec<ZkScalar> {
+        let mut prod = vec![ZkScalar::zero(); a.len() + b.len()];
+        for (i, a) in a.iter().enumerate() {
+            for (j, b) in b.iter().enumerate() {
+                prod[i + j] += *a * b;
+            }
+        }
+        poly_rem(&prod, f)
+    }
+
+    // a^p mod f, where p is the field size
+    fn poly_frobenius(a: &[ZkScalar], f: &[ZkScalar]) -> Vec<ZkScalar> {
+        let exp = (-ZkScalar::one()).to_repr();
+        let exp = exp.as_ref();
+        let mut result = vec![ZkScalar::one()];
+        for i in (0..exp.len() * 8).rev() {
+            result = poly_mul_rem(&result, &result, f);
+            if (exp[i / 8] >> (i % 8)) & 1 == 1 {
+                result = poly_mul_rem(&result, a, f);
+            }
+        }
+        poly_mul_rem(&result, a, f)
+    }
+
+    // A quartic is irreducible iff it has no factors of degree 1 or 2, i.e. iff
+    // it's coprime with x^(p^2) - x
+    fn is_irreducible_quartic(f: &[ZkScalar]) -> bool {
+        assert_eq!(f.len(), 5);
+        let x = [ZkScalar::zero(), ZkScalar::one()];
+        let mut b = poly_frobenius(&poly_frobenius(&x, f), f);
+        b.resize(std::cmp::max(b.len(), 2), ZkScalar::zero());
+        b[1] -= ZkScalar::one();
+        let (mut a, mut b) = (f.to_vec(), poly_rem(&b, f));
+        while !b.is_empty() {
+            let r = poly_rem(&a, &b);
+            a = b;
+            b = r;
+        }
+        a.len() == 1
+    }
+
+    #[test]
+    fn test_poseidon2_internal_matrix() {
+        let ones_plus = |diag: [u64; 4]| -> Matrix {
+            (0..4)
+                .map(|i| {
+                    (0..4)
+                        .map(|j| ZkScalar::from(1 + if i == j { diag[i] } else { 0 }))
+                        .collect()
+                })
+                .collect()
+        };
+        let m = &POSEIDON2_WIDTH4.internal_matrix;
+        assert_eq!(*m, ones_plus([1, 3, 5, 6]));
+        let mut power = m.clone();
+        for _ in 0..2 * m.len() {
+            assert!(is_irreducible_quartic(&char_poly(&power)));
+            power = mat_mul(&power, m);
+        }
+        assert!(!is_irreducible_quartic(&char_poly(&ones_plus([
+            1, 2, 3, 4
+        ]))));
+    }
+
+    #[test]
+    fn test_poseidon2_empty_input_panics() {
+        assert!(std::panic::catch_unwind(|| super::super::hash(&[])).is_err());
+        assert!(std::panic::catch_unwind(|| {
+            hash(&mut TestConstraintSystem::<BellmanFr>::new(), &[])
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_poseidon2_permutation() {
+        let params = &*POSEIDON2_WIDTH4;
+        let vals = (0..4)
+            .map(|i| ZkScalar::from(i as u64 * 17 + 5))
+            .collect::<Vec<_>>();
+        let mut expected = vals.clone();
+        params.permute(&mut expected);
+        assert_ne!(expected, vals);
+
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let nums = alloc_vals(&mut cs.namespace(|| "vals"), &vals);
+        let out = permute(
+            &mut cs.namespace(|| "permute"),
+            params,
+            nums.into_iter().map(WrappedLc::alloc_num).collect(),
+        )
+        .unwrap();
+        for (out, expected) in out.iter().zip(expected.iter()) {
+            assert_eq!(out.get_value(), Some((*expected).into()));
+        }
+        assert!(cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_poseidon2_hash() {
+        for len in 1..9 {
+            let vals = (0..len)
+                .map(|i| ZkScalar::from(i as u64 + 100))
+                .collect::<Vec<_>>();
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let nums = alloc_vals(&mut cs.namespace(|| "vals"), &vals);
+            let out = hash(&mut cs.namespace(|| "hash"), &nums).unwrap();
+            assert_eq!(out.get_value(), Some(super::super::hash(&vals).into()));
+            assert!(cs.is_satisfied());
+            if len > 1 {
+                cs.set("vals/val 1/num", BellmanFr::from(1));
+                assert!(!cs.is_satisfied());
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon2_cheaper_than_poseidon4() {
+        let vals = (0..4).map(|i| ZkScalar::from(i as u64)).collect::<Vec<_>>();
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let nums = alloc_vals(&mut cs.namespace(|| "vals"), &vals);
+        let before = cs.num_constraints();
+        let out = hash4(
+            &mut cs.namespace(|| "poseidon2"),
+            nums[0].clone(),
+            nums[1].clone(),
+            nums[2].clone(),
+            nums[3].clone(),
+        )
+        .unwrap();
+        let poseidon2_constraints = cs.num_constraints() - before;
+        assert_eq!(
+            out.get_value(),
+            Some(super::super::hash4(vals[0], vals[1], vals[2], vals[3]).into())
+        );
+        poseidon4(
+            &mut cs.namespace(|| "poseidon4"),
+            nums[0].clone(),
+            nums[1].clone(),
+            nums[2].clone(),
+            nums[3].clone(),
+        )
+        .unwrap();
+        let poseidon4_constraints = cs.num_constraints() - before - poseidon2_constraints;
+        assert!(cs.is_satisfied());
+        assert_eq!(poseidon2_constraints, 265);
+        assert_eq!(poseidon4_constraints, 301);
+    }
+}
@@ -0,0 +1,147 @@
+#[cfg(feature = "groth16")]
+pub mod groth16;
+
+use crate::poseidon::{sbox, Grain};
+use bazuka::zk::{ZkHasher, ZkScalar};
+use ff::Field;
+
+// Parameters of a Poseidon2 permutation over ZkScalar with x^5 sbox
+#[derive(Debug, Clone)]
+pub struct Poseidon2Params {
+    pub width: usize,
+    pub full_rounds: usize,
+    pub partial_rounds: usize,
+    pub external_constants: Vec<ZkScalar>,
+    pub internal_constants: Vec<ZkScalar>,
+    pub external_matrix: Vec<Vec<ZkScalar>>,
+    pub internal_matrix: Vec<Vec<ZkScalar>>,
+}
+
+fn to_matrix(vals: &[&[u64]]) -> Vec<Vec<ZkScalar>> {
+    vals.iter()
+        .map(|row| row.iter().map(|v| ZkScalar::from(*v)).collect())
+        .collect()
+}
+
+fn product_matrix(matrix: &[Vec<ZkScalar>], state: &mut [ZkScalar]) {
+    let mixed = matrix
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(state.iter())
+                .fold(ZkScalar::zero(), |acc, (m, s)| acc + *m * s)
+        })
+        .collect::<Vec<_>>();
+    state.copy_from_slice(&mixed);
+}
+
+impl Poseidon2Params {
+    // Width 4 instance specific to zeekit, it is not the reference BLS12-381 t=4
+    // instance of the Poseidon2 authors and doesn't reproduce its test vectors
+    // Round constants are drawn from the Grain LFSR in round order, following the
+    // procedure of the paper (Partial rounds only keep the first constant of their row)
+    // External matrix is the M4 matrix of the paper. The internal matrix is the
+    // all-ones matrix plus a diagonal, chosen here such that M_I^k has an irreducible
+    // minimal polynomial of degree 4 for k up to 8 (No arbitrarily long subspace
+    // trails); diag(1, 3, 5, 6) is the first such diagonal, ordered by largest entry
+    // and then lexicographically
+    pub fn generate_width4(full_rounds: usize, partial_rounds: usize) -> Self {
+        let width = 4;
+        let half_full = full_rounds / 2;
+        let mut grain = Grain::new(width, full_rounds, partial_rounds);
+        let mut external_constants = Vec::new();
+        let mut internal_constants = Vec::new();
+        for round in 0..full_rounds + partial_rounds {
+            let row = (0..width).map(|_| grain.next_scalar()).collect::<Vec<_>>();
+            if round < half_full || round >= half_full + partial_rounds {
+                external_constants.extend(row);
+            } else {
+                internal_constants.push(row[0]);
+            }
+        }
+        Self {
+            width,
+            full_rounds,
+            partial_rounds,
+            external_constants,
+            internal_constants,
+            external_matrix: to_matrix(&[
+                &[5, 7, 1, 3],
+                &[4, 6, 1, 1],
+                &[1, 3, 5, 7],
+                &[1, 1, 4, 6],
+            ]),
+            internal_matrix: to_matrix(&[
+                &[2, 1, 1, 1],
+                &[1, 4, 1, 1],
+                &[1, 1, 6, 1],
+                &[1, 1, 1, 7],
+            ]),
+        }
+    }
+
+    pub fn permute(&self, state: &mut [ZkScalar]) {
+        assert_eq!(state.len(), self.width);
+        product_matrix(&self.external_matrix, state);
+        let half_full = self.full_rounds / 2;
+        let mut external_constants = self.external_constants.chunks(self.width);
+        for _ in 0..half_full {
+            self.external_round(state, external_constants.next().unwrap());
+        }
+        for constant in self.internal_constants.iter() {
+            state[0] = sbox(state[0] + constant);
+            product_matrix(&self.internal_matrix, state);
+        }
+        for _ in half_full..self.full_rounds {
+            self.external_round(state, external_constants.next().unwrap());
+        }
+    }
+
+    fn external_round(&self, state: &mut [ZkScalar], constants: &[ZkScalar]) {
+        for (s, c) in state.iter_mut().zip(constants.iter()) {
+            *s = sbox(*s + c);
+        }
+        product_matrix(&self.external_matrix, state);
+    }
+
+    // Compression mode, permutation plus feed-forward truncated to one element
+    pub fn compress(&self, vals: &[ZkScalar]) -> ZkScalar {
+        assert_eq!(vals.len(), self.width);
+        let mut state = vals.to_vec();
+        self.permute(&mut state);
+        state[0] + vals[0]
+    }
+}
+
+lazy_static::lazy_static! {
+    // zeekit's own instance, see generate_width4
+    pub static ref POSEIDON2_WIDTH4: Poseidon2Params = Poseidon2Params::generate_width4(8, 56);
+}
+
+pub fn hash4(a: ZkScalar, b: ZkScalar, c: ZkScalar, d: ZkScalar) -> ZkScalar {
+    POSEIDON2_WIDTH4.compress(&[a, b, c, d])
+}
+
+// Chained hash4, with the same chaining rule as bazuka's native poseidon
+// vals must not be empty
+pub fn hash(vals: &[ZkScalar]) -> ZkScalar {
+    assert!(!vals.is_empty());
+    let mut first = vals[0];
+    for chunk in vals[1..].chunks(3) {
+        let mut inputs = vec![first];
+        inputs.extend_from_slice(chunk);
+        inputs.resize(4, ZkScalar::zero());
+        first = POSEIDON2_WIDTH4.compress(&inputs);
+    }
+    first
+}
+
+// Poseidon2 alternative of bazuka's PoseidonHasher, for state trees
+#[derive(Debug, Clone)]
+pub struct Poseidon2Hasher;
+
+impl ZkHasher for Poseidon2Hasher {
+    fn hash(vals: &[ZkScalar]) -> ZkScalar {
+        hash(vals)
+    }
+}
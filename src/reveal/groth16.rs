@@ -1,6 +1,6 @@
-use crate::poseidon::groth16::{poseidon, poseidon4};
+use crate::poseidon::groth16::GadgetHasher;
 use crate::BellmanFr;
-use bazuka::zk::ZkStateModel;
+use bazuka::zk::{PoseidonHasher, ZkStateModel};
 use bellman::gadgets::num::AllocatedNum;
 use bellman::{ConstraintSystem, SynthesisError};
 
@@ -14,6 +14,15 @@ pub fn reveal<CS: ConstraintSystem<BellmanFr>>(
     cs: &mut CS,
     state_model: ZkStateModel,
     state: AllocatedState,
+) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
+    reveal_with_hasher::<PoseidonHasher, CS>(cs, state_model, state)
+}
+
+// Same as ZkStateBuilder<H> compression
+pub fn reveal_with_hasher<H: GadgetHasher, CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    state_model: ZkStateModel,
+    state: AllocatedState,
 ) -> Result<AllocatedNum<BellmanFr>, SynthesisError> {
     match state_model {
         ZkStateModel::Scalar => {
@@ -26,13 +35,17 @@ pub fn reveal<CS: ConstraintSystem<BellmanFr>>(
         ZkStateModel::Struct { field_types } => {
             let mut vals = Vec::new();
             if let AllocatedState::Children(children) = state {
-                for (field_type, field_value) in field_types.iter().zip(children.into_iter()) {
-                    vals.push(reveal(&mut *cs, field_type.clone(), field_value)?);
+                for (i, (field_type, field_value)) in field_types.iter().zip(children).enumerate() {
+                    vals.push(reveal_with_hasher::<H, _>(
+                        &mut cs.namespace(|| format!("field {}", i)),
+                        field_type.clone(),
+                        field_value,
+                    )?);
                 }
             } else {
                 panic!("Invalid state!");
             }
-            poseidon(&mut *cs, &vals)
+            H::hash_gadget(&mut cs.namespace(|| "hash"), &vals)
         }
         ZkStateModel::List {
             log4_size,
@@ -41,23 +54,26 @@ pub fn reveal<CS: ConstraintSystem<BellmanFr>>(
             let mut leaves = Vec::new();
             if let AllocatedState::Children(children) = state {
                 for i in 0..1 << (2 * log4_size) {
-                    leaves.push(reveal(&mut *cs, *item_type.clone(), children[i].clone())?);
+                    leaves.push(reveal_with_hasher::<H, _>(
+                        &mut cs.namespace(|| format!("item {}", i)),
+                        *item_type.clone(),
+                        children[i].clone(),
+                    )?);
                 }
             } else {
                 panic!("Invalid state!");
             }
+            let mut level = 0;
             while leaves.len() != 1 {
                 let mut new_leaves = Vec::new();
-                for chunk in leaves.chunks(4) {
-                    new_leaves.push(poseidon4(
-                        &mut *cs,
-                        chunk[0].clone(),
-                        chunk[1].clone(),
-                        chunk[2].clone(),
-                        chunk[3].clone(),
+                for (i, chunk) in leaves.chunks(4).enumerate() {
+                    new_leaves.push(H::hash_gadget(
+                        &mut cs.namespace(|| format!("level {} node {}", level, i)),
+                        chunk,
                     )?);
                 }
                 leaves = new_leaves;
+                level += 1;
             }
             Ok(leaves[0].clone())
         }
@@ -67,9 +83,11 @@ pub fn reveal<CS: ConstraintSystem<BellmanFr>>(
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::poseidon2::Poseidon2Hasher;
     use crate::Bls12;
     use bazuka::core::ZkHasher;
     use bazuka::zk::{ZkDataLocator, ZkDataPairs, ZkScalar, ZkStateBuilder};
+    use bellman::gadgets::test::TestConstraintSystem;
     use bellman::{groth16, Circuit, ConstraintSystem, SynthesisError};
     use rand::rngs::OsRng;
 
@@ -87,7 +105,7 @@ mod test {
     ) -> Result<AllocatedState, SynthesisError> {
         match state_model {
             ZkStateModel::Scalar => {
-                let num = AllocatedNum::alloc(&mut *cs, || {
+                let num = AllocatedNum::alloc(cs.namespace(|| format!("{:?}", locator.0)), || {
                     if let Some(pairs) = pairs {
                         Ok(pairs
                             .0
@@ -207,4 +225,68 @@ mod test {
         let proof = groth16::create_random_proof(c, &params, &mut OsRng).unwrap();
         assert!(groth16::verify_proof(&pvk, &proof, &[expected.into()]).is_ok());
     }
+
+    #[test]
+    fn test_reveal_with_poseidon2() {
+        let state_model = ZkStateModel::Struct {
+            field_types: vec![
+                ZkStateModel::Scalar,
+                ZkStateModel::List {
+                    item_type: Box::new(ZkStateModel::Struct {
+                        field_types: vec![ZkStateModel::Scalar, ZkStateModel::Scalar],
+                    }),
+                    log4_size: 2,
+                },
+                ZkStateModel::Scalar,
+            ],
+        };
+        let data = ZkDataPairs(
+            [
+                (ZkDataLocator(vec![0]), ZkScalar::from(123)),
+                (ZkDataLocator(vec![1, 3, 0]), ZkScalar::from(10)),
+                (ZkDataLocator(vec![1, 3, 1]), ZkScalar::from(11)),
+                (ZkDataLocator(vec![1, 15, 1]), ZkScalar::from(15)),
+                (ZkDataLocator(vec![2]), ZkScalar::from(234)),
+            ]
+            .into(),
+        );
+
+        let mut builder = ZkStateBuilder::<Poseidon2Hasher>::new(state_model.clone());
+        builder.batch_set(&data.as_delta()).unwrap();
+        let expected = builder.compress().unwrap().state_hash;
+
+        let mut constraints = Vec::new();
+        for poseidon2 in [false, true] {
+            let mut cs = TestConstraintSystem::<BellmanFr>::new();
+            let alloc_state = extract_witnesses(
+                &mut cs.namespace(|| "state"),
+                state_model.clone(),
+                ZkDataLocator(vec![]),
+                &Some(data.clone()),
+            )
+            .unwrap();
+            let root = if poseidon2 {
+                reveal_with_hasher::<Poseidon2Hasher, _>(
+                    &mut cs.namespace(|| "reveal"),
+                    state_model.clone(),
+                    alloc_state,
+                )
+                .unwrap()
+            } else {
+                reveal(
+                    &mut cs.namespace(|| "reveal"),
+                    state_model.clone(),
+                    alloc_state,
+                )
+                .unwrap()
+            };
+            if poseidon2 {
+                assert_eq!(root.get_value(), Some(expected.into()));
+            }
+            assert!(cs.is_satisfied());
+            constraints.push(cs.num_constraints());
+        }
+        // 16 items, 5 list nodes and the root struct, all hashed with a single permutation
+        assert_eq!(constraints[0] - constraints[1], (16 + 5 + 1) * (301 - 265));
+    }
 }
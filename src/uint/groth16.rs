@@ -0,0 +1,331 @@
+use crate::common::groth16::{
+    assert_lt, from_bits, gt, gte, lt, lte_bits, range_check, to_bits, WrappedLc,
+};
+use crate::BellmanFr;
+
+use bellman::gadgets::boolean::AllocatedBit;
+use bellman::gadgets::num::AllocatedNum;
+use bellman::{ConstraintSystem, SynthesisError};
+use ff::PrimeField;
+
+pub type AllocatedUInt64 = AllocatedUInt<64>;
+pub type AllocatedUInt128 = AllocatedUInt<128>;
+
+fn fr_from_u128(v: u128) -> BellmanFr {
+    BellmanFr::from_raw([v as u64, (v >> 64) as u64, 0, 0])
+}
+
+fn fr_to_u128(v: BellmanFr) -> Option<u128> {
+    let repr = v.to_repr();
+    if repr[16..].iter().all(|b| *b == 0) {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&repr[..16]);
+        Some(u128::from_le_bytes(bytes))
+    } else {
+        None
+    }
+}
+
+// An unsigned integer of BITS bits, every instance is range-checked so arithmetic
+// on it can't silently wrap around the field modulus
+#[derive(Clone)]
+pub struct AllocatedUInt<const BITS: usize>(AllocatedNum<BellmanFr>);
+
+impl<const BITS: usize> AllocatedUInt<BITS> {
+    // BITS constraints
+    pub fn alloc<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        value: Option<u128>,
+    ) -> Result<Self, SynthesisError> {
+        let num = AllocatedNum::alloc(&mut *cs, || {
+            value
+                .map(fr_from_u128)
+                .ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        Self::from_num(cs, num)
+    }
+
+    // Range-check an existing number, BITS constraints
+    pub fn from_num<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        num: AllocatedNum<BellmanFr>,
+    ) -> Result<Self, SynthesisError> {
+        assert!(BITS <= 128);
+        range_check(
+            &mut cs.namespace(|| "range"),
+            &WrappedLc::alloc_num(num.clone()),
+            BITS,
+        )?;
+        Ok(Self(num))
+    }
+
+    pub fn get_num(&self) -> &AllocatedNum<BellmanFr> {
+        &self.0
+    }
+
+    pub fn get_value(&self) -> Option<u128> {
+        self.0.get_value().and_then(fr_to_u128)
+    }
+
+    fn from_lc<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        val: WrappedLc,
+    ) -> Result<Self, SynthesisError> {
+        let num = AllocatedNum::alloc(&mut *cs, || {
+            val.get_value().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        cs.enforce(
+            || "val == num",
+            |lc| lc + &val.0,
+            |lc| lc + CS::one(),
+            |lc| lc + num.get_variable(),
+        );
+        Self::from_num(cs, num)
+    }
+
+    // Unsatisfiable on overflow, BITS + 2 constraints
+    pub fn add<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &Self,
+    ) -> Result<Self, SynthesisError> {
+        let mut sum = WrappedLc::alloc_num(self.0.clone());
+        sum.add_num(&other.0);
+        Self::from_lc(cs, sum)
+    }
+
+    // Unsatisfiable on underflow, BITS + 2 constraints
+    pub fn sub<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &Self,
+    ) -> Result<Self, SynthesisError> {
+        let diff = WrappedLc::alloc_num(self.0.clone()) - WrappedLc::alloc_num(other.0.clone());
+        Self::from_lc(cs, diff)
+    }
+
+    // Unsatisfiable on overflow
+    // BITS + 1 constraints, or ~3 * BITS when the full product may not fit in the field
+    pub fn mul<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &Self,
+    ) -> Result<Self, SynthesisError> {
+        if 2 * BITS > BellmanFr::CAPACITY as usize {
+            // The product of the high halves must be zero, so that the real product
+            // stays far below the field modulus and can't wrap around it
+            let half = BITS / 2;
+            let self_bits = to_bits(&mut cs.namespace(|| "self bits"), self.0.clone(), BITS)?;
+            let other_bits = to_bits(&mut cs.namespace(|| "other bits"), other.0.clone(), BITS)?;
+            let self_high = from_bits(
+                &mut cs.namespace(|| "self high"),
+                self_bits[half..].to_vec(),
+            )?;
+            let other_high = from_bits(
+                &mut cs.namespace(|| "other high"),
+                other_bits[half..].to_vec(),
+            )?;
+            cs.enforce(
+                || "self_high * other_high == 0",
+                |lc| lc + self_high.get_variable(),
+                |lc| lc + other_high.get_variable(),
+                |lc| lc,
+            );
+        }
+        let prod = self.0.mul(cs.namespace(|| "prod"), &other.0)?;
+        Self::from_num(cs, prod)
+    }
+
+    // Returns (self / other, self % other), unsatisfiable when other is zero
+    // ~6 * BITS constraints, ~8 * BITS for wide integers
+    pub fn div_mod<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &Self,
+    ) -> Result<(Self, Self), SynthesisError> {
+        let quot_rem = self
+            .get_value()
+            .zip(other.get_value())
+            .map(|(a, b)| {
+                a.checked_div(b)
+                    .zip(a.checked_rem(b))
+                    .ok_or(SynthesisError::DivisionByZero)
+            })
+            .transpose()?;
+        let quot = Self::alloc(&mut cs.namespace(|| "quot"), quot_rem.map(|(q, _)| q))?;
+        let rem = Self::alloc(&mut cs.namespace(|| "rem"), quot_rem.map(|(_, r)| r))?;
+
+        // other * quot + rem == self, where other * quot can't overflow
+        let prod = other.mul(&mut cs.namespace(|| "other * quot"), &quot)?;
+        cs.enforce(
+            || "prod + rem == self",
+            |lc| lc + prod.0.get_variable() + rem.0.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + self.0.get_variable(),
+        );
+
+        // rem < other, which also rules out other == 0
+        assert_lt(
+            &mut cs.namespace(|| "rem < other"),
+            rem.0.clone(),
+            other.0.clone(),
+            BITS,
+        )?;
+
+        Ok((quot, rem))
+    }
+
+    pub fn lt<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &Self,
+    ) -> Result<AllocatedBit, SynthesisError> {
+        lt(cs, self.0.clone(), other.0.clone(), BITS)
+    }
+
+    pub fn lte<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &Self,
+    ) -> Result<AllocatedBit, SynthesisError> {
+        lte_bits(cs, self.0.clone(), other.0.clone(), BITS)
+    }
+
+    pub fn gt<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &Self,
+    ) -> Result<AllocatedBit, SynthesisError> {
+        gt(cs, self.0.clone(), other.0.clone(), BITS)
+    }
+
+    pub fn gte<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &Self,
+    ) -> Result<AllocatedBit, SynthesisError> {
+        gte(cs, self.0.clone(), other.0.clone(), BITS)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bellman::gadgets::test::TestConstraintSystem;
+    use num_bigint::BigUint;
+
+    type Op<const BITS: usize> = fn(
+        &AllocatedUInt<BITS>,
+        &mut TestConstraintSystem<BellmanFr>,
+        &AllocatedUInt<BITS>,
+    ) -> Result<AllocatedUInt<BITS>, SynthesisError>;
+
+    fn check_op<const BITS: usize>(
+        op: Op<BITS>,
+        native: fn(u128, u128) -> Option<u128>,
+        a: u128,
+        b: u128,
+    ) {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a_uint = AllocatedUInt::<BITS>::alloc(&mut cs.namespace(|| "a"), Some(a)).unwrap();
+        let b_uint = AllocatedUInt::<BITS>::alloc(&mut cs.namespace(|| "b"), Some(b)).unwrap();
+        let out = op(&a_uint, &mut cs, &b_uint).unwrap();
+        let expected = native(a, b).filter(|v| BITS == 128 || *v >> BITS == 0);
+        assert_eq!(cs.is_satisfied(), expected.is_some());
+        if expected.is_some() {
+            assert_eq!(out.get_value(), expected);
+        }
+    }
+
+    fn check_ops<const BITS: usize>(vals: &[u128]) {
+        for a in vals.iter().cloned() {
+            for b in vals.iter().cloned() {
+                check_op::<BITS>(AllocatedUInt::add, u128::checked_add, a, b);
+                check_op::<BITS>(AllocatedUInt::sub, u128::checked_sub, a, b);
+                check_op::<BITS>(AllocatedUInt::mul, u128::checked_mul, a, b);
+
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let a_uint =
+                    AllocatedUInt::<BITS>::alloc(&mut cs.namespace(|| "a"), Some(a)).unwrap();
+                let b_uint =
+                    AllocatedUInt::<BITS>::alloc(&mut cs.namespace(|| "b"), Some(b)).unwrap();
+                let div_mod = a_uint.div_mod(&mut cs.namespace(|| "div_mod"), &b_uint);
+                match a.checked_div(b) {
+                    Some(q) => {
+                        let (q_uint, r_uint) = div_mod.unwrap();
+                        assert_eq!(q_uint.get_value(), Some(q));
+                        assert_eq!(r_uint.get_value(), Some(a % b));
+                        assert!(cs.is_satisfied());
+                    }
+                    None => {
+                        assert!(matches!(div_mod, Err(SynthesisError::DivisionByZero)));
+                    }
+                }
+
+                let mut cs = TestConstraintSystem::<BellmanFr>::new();
+                let a_uint =
+                    AllocatedUInt::<BITS>::alloc(&mut cs.namespace(|| "a"), Some(a)).unwrap();
+                let b_uint =
+                    AllocatedUInt::<BITS>::alloc(&mut cs.namespace(|| "b"), Some(b)).unwrap();
+                let lt = a_uint.lt(&mut cs.namespace(|| "lt"), &b_uint).unwrap();
+                let gte = a_uint.gte(&mut cs.namespace(|| "gte"), &b_uint).unwrap();
+                assert_eq!(lt.get_value(), Some(a < b));
+                assert_eq!(gte.get_value(), Some(a >= b));
+                assert!(cs.is_satisfied());
+            }
+        }
+    }
+
+    #[test]
+    fn test_uint64() {
+        let max = u64::MAX as u128;
+        check_ops::<64>(&[0, 1, 2, 12345, 1 << 32, max / 2, max - 1, max]);
+    }
+
+    #[test]
+    fn test_uint128() {
+        let max = u128::MAX;
+        check_ops::<128>(&[0, 1, 2, 12345, 1 << 64, max / 2, max - 1, max]);
+    }
+
+    #[test]
+    fn test_uint_rejects_out_of_range() {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        AllocatedUInt64::alloc(&mut cs, Some(1 << 64)).unwrap();
+        assert!(!cs.is_satisfied());
+
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let num = AllocatedNum::alloc(cs.namespace(|| "num"), || Ok(-BellmanFr::one())).unwrap();
+        AllocatedUInt128::from_num(&mut cs.namespace(|| "uint"), num).unwrap();
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_uint128_mul_rejects_field_wraparound() {
+        // a * b == p + c for some c < 2^128, so the product reduced by the field
+        // modulus would pass a plain range-check
+        let p = BigUint::parse_bytes(
+            b"52435875175126190479447740508185965837690552500527637822603658699938581184513",
+            10,
+        )
+        .unwrap();
+        let a = BigUint::from(3u8) << 126;
+        let c = (&a - &p % &a) % &a;
+        let b = (&p + &c) / &a;
+        let a = u128::try_from(&a).unwrap();
+        let b = u128::try_from(&b).unwrap();
+        let c = u128::try_from(&c).unwrap();
+        assert_eq!(fr_from_u128(a) * fr_from_u128(b), fr_from_u128(c));
+
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let a_uint = AllocatedUInt128::alloc(&mut cs.namespace(|| "a"), Some(a)).unwrap();
+        let b_uint = AllocatedUInt128::alloc(&mut cs.namespace(|| "b"), Some(b)).unwrap();
+        let prod = a_uint.mul(&mut cs.namespace(|| "mul"), &b_uint).unwrap();
+        assert_eq!(prod.get_value(), Some(c));
+        assert!(!cs.is_satisfied());
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some("mul/self_high * other_high == 0")
+        );
+    }
+}
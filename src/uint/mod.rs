@@ -0,0 +1,2 @@
+#[cfg(feature = "groth16")]
+pub mod groth16;